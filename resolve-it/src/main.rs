@@ -2,21 +2,125 @@ extern crate serde_path_to_error;
 extern crate supertimeline;
 extern crate supertimeline_json;
 
+use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::process;
 use std::time::Instant;
 use supertimeline::get_state;
 use supertimeline::resolve_all_states;
 use supertimeline::resolve_timeline;
+use supertimeline::LayerUsage;
 use supertimeline::ResolveOptions;
+use supertimeline::Time;
 
 use supertimeline_json::object::JsonTimelineObject;
 
+fn load_timeline(filename: &str) -> Vec<JsonTimelineObject> {
+    let raw_tl = fs::read_to_string(filename).unwrap();
+    parse_timeline(&raw_tl)
+}
+
+fn parse_timeline(raw_tl: &str) -> Vec<JsonTimelineObject> {
+    let processed_tl = supertimeline_json::hack::mangle_json_enable(raw_tl).unwrap();
+
+    let mut deserializer = serde_json::Deserializer::from_str(&processed_tl);
+    serde_path_to_error::deserialize(&mut deserializer).unwrap()
+}
+
+/** Analyzes the layers between the two times. All repeats before the end are resolved, so repeating objects don't run out early.
+ * Returns the time the analysis stops at as well, which is before the end if the timeline could not be resolved that far
+ */
+fn analyze_layers(
+    timeline: &[JsonTimelineObject],
+    from: Time,
+    to: Time,
+    layers: &[&str],
+) -> (HashMap<String, LayerUsage>, Time) {
+    let options = ResolveOptions {
+        time: from,
+        limit_count: Some(usize::MAX),
+        limit_time: Some(to),
+    };
+    let resolved = resolve_timeline(timeline, options).unwrap();
+    let states = resolve_all_states(&resolved, None).unwrap();
+
+    // Past valid_until the layers may only look empty because the repeats were cut off
+    let checked_until = states
+        .valid_until
+        .map_or(to, |valid_until| valid_until.min(to));
+    (
+        states.layer_usage(layers, from, checked_until),
+        checked_until,
+    )
+}
+
+/** Checks that the mandatory layers are never empty between the two times. Exits with an error if any of them are,
+ * or if the timeline could not be resolved far enough to check all of it
+ */
+fn check_gaps(filename: &str, from: Time, to: Time, layers: &[&str]) {
+    let parsed = load_timeline(filename);
+
+    let (usage, checked_until) = analyze_layers(&parsed, from, to, layers);
+    let mut has_gaps = false;
+    for layer in layers {
+        let usage = &usage[*layer];
+        println!(
+            "Layer {}: {:.1}% occupied, {} switches, {} gaps",
+            layer,
+            usage.occupied_percent,
+            usage.switches,
+            usage.gaps.len()
+        );
+        if let Some(item) = &usage.shortest_item {
+            println!(
+                "  Shortest item: {} ({}ms from {})",
+                item.object_id,
+                item.end - item.start,
+                item.start
+            );
+        }
+        for gap in &usage.gaps {
+            println!(
+                "  Gap from {} to {} ({}ms)",
+                gap.start,
+                gap.end,
+                gap.end - gap.start
+            );
+        }
+
+        has_gaps = has_gaps || !usage.gaps.is_empty();
+    }
+
+    if has_gaps {
+        println!("Mandatory layers have gaps");
+        process::exit(1);
+    }
+    if checked_until < to {
+        println!(
+            "The timeline could only be resolved until {}, the layers could not be checked after that",
+            checked_until
+        );
+        process::exit(1);
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 3 {
-        println!("Usage: dump.json 10")
+    if args.len() >= 2 && args[1] == "check-gaps" {
+        if args.len() < 6 {
+            println!("Usage: check-gaps dump.json from to layer [layer...]");
+            process::exit(2);
+        }
+
+        let from = args[3].parse::<Time>().unwrap();
+        let to = args[4].parse::<Time>().unwrap();
+        let layers = args[5..].iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        check_gaps(&args[2], from, to, &layers);
+    } else if args.len() < 3 {
+        println!("Usage: dump.json 10");
+        println!("   or: check-gaps dump.json from to layer [layer...]");
     } else {
         let filename = &args[1];
         let iterations = args[2].parse::<usize>().unwrap();
@@ -142,3 +246,45 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_gaps_with_repeating_object() {
+        let timeline = parse_timeline(
+            r#"[{
+                "id": "a",
+                "layer": "pgm",
+                "enable": { "start": 0, "duration": 100, "repeating": 100 },
+                "content": {},
+                "priority": 0
+            }]"#,
+        );
+
+        // Every repeat in the window is resolved, not just the default limit
+        let (usage, checked_until) = analyze_layers(&timeline, 0, 1000, &["pgm"]);
+        assert_eq!(checked_until, 1000);
+        assert!(usage["pgm"].gaps.is_empty());
+        assert_eq!(usage["pgm"].occupied_percent, 100.0);
+    }
+
+    #[test]
+    fn check_gaps_with_limited_object() {
+        let timeline = parse_timeline(
+            r#"[{
+                "id": "a",
+                "layer": "pgm",
+                "enable": { "start": 0, "duration": 100, "repeating": 100 },
+                "content": {},
+                "priority": 0,
+                "limitCount": 2
+            }]"#,
+        );
+
+        // The limit of the object wins, so the end is not reached
+        let (_, checked_until) = analyze_layers(&timeline, 0, 1000, &["pgm"]);
+        assert!(checked_until < 1000);
+    }
+}
@@ -21,8 +21,8 @@ struct MyObj {
 struct TimelineLayerState2{
     pub object_id: String,
     pub raw_object: Option<JsonTimelineObject>,
-    pub instance_id: Option<String>, // TODO - these both being Option<> is horrible
-    pub instance: Option<TimelineObjectInstance>, // TODO - this is a bit heavy now?
+    pub instance_id: String,
+    pub instance: TimelineObjectInstance,
     pub keyframes: Vec<TimelineLayerState2Keyframe>,
 }
 
@@ -35,22 +35,13 @@ pub struct TimelineLayerState2Keyframe {
 
 fn transform_state(timeline: &HashMap<String,JsonTimelineObject>, st: Option<TimelineLayerState>)-> Option<TimelineLayerState2> {
     if let Some(st) = st {
-        let instance = {
-            if let Some(inst) = st.instance {
-                let a = inst.try_lock().unwrap(); // TODO - eww
-                Some(a.clone()) // Clone to avoid holding a lock
-            } else {
-                None
-            }
-        };
-
         let raw_object = timeline.get(&st.object_id).cloned();
 
         let mut res = TimelineLayerState2{
             object_id: st.object_id,
             raw_object,
-            instance_id: st.instance_id,
-            instance,
+            instance_id: st.instance.id.clone(),
+            instance: st.instance,
             keyframes: vec!(),
         };
 
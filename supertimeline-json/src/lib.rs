@@ -6,3 +6,4 @@ extern crate serde_path_to_error;
 
 pub mod hack;
 pub mod object;
+pub mod state;
@@ -1,7 +1,13 @@
 use serde::{Deserialize, Serialize};
+use supertimeline::ClassInheritance;
+use supertimeline::Expression;
+use supertimeline::GroupMode;
 use supertimeline::IsTimelineKeyframe;
 use supertimeline::IsTimelineObject;
+use supertimeline::Time;
 use supertimeline::TimelineEnable;
+use supertimeline::TimelinePause;
+use supertimeline::TimelinePrioritySegment;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct JsonTimelineObjectKeyframe {
@@ -12,6 +18,10 @@ pub struct JsonTimelineObjectKeyframe {
     #[serde(default)]
     pub disabled: bool,
     pub content: serde_json::Value,
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Expression>,
 }
 impl IsTimelineKeyframe for JsonTimelineObjectKeyframe {
     fn id(&self) -> &str {
@@ -26,6 +36,12 @@ impl IsTimelineKeyframe for JsonTimelineObjectKeyframe {
     fn disabled(&self) -> bool {
         self.disabled
     }
+    fn priority(&self) -> i64 {
+        self.priority
+    }
+    fn duration(&self) -> Option<&Expression> {
+        self.duration.as_ref()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq,Clone)]
@@ -45,6 +61,40 @@ pub struct JsonTimelineObject {
     pub children: Option<Vec<JsonTimelineObject>>,
     #[serde(default)]
     pub priority: i64,
+    #[serde(rename = "limitCount", default, skip_serializing_if = "Option::is_none")]
+    pub limit_count: Option<usize>,
+    #[serde(rename = "limitTime", default, skip_serializing_if = "Option::is_none")]
+    pub limit_time: Option<Time>,
+    #[serde(
+        rename = "transitionDuration",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub transition_duration: Option<Time>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pauses: Option<Vec<TimelinePause>>,
+    #[serde(
+        rename = "playbackRate",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub playback_rate: Option<f64>,
+    #[serde(rename = "groupMode", default, skip_serializing_if = "Option::is_none")]
+    pub group_mode: Option<GroupMode>,
+    #[serde(rename = "outlivesParent", default)]
+    pub outlives_parent: bool,
+    #[serde(
+        rename = "classInheritance",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub class_inheritance: Option<ClassInheritance>,
+    #[serde(
+        rename = "prioritySegments",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub priority_segments: Option<Vec<TimelinePrioritySegment>>,
 }
 impl IsTimelineObject<JsonTimelineObject, JsonTimelineObjectKeyframe> for JsonTimelineObject {
     fn id(&self) -> &str {
@@ -71,6 +121,33 @@ impl IsTimelineObject<JsonTimelineObject, JsonTimelineObjectKeyframe> for JsonTi
     fn priority(&self) -> i64 {
         self.priority
     }
+    fn limit_count(&self) -> Option<usize> {
+        self.limit_count
+    }
+    fn limit_time(&self) -> Option<Time> {
+        self.limit_time
+    }
+    fn transition_duration(&self) -> Option<Time> {
+        self.transition_duration
+    }
+    fn pauses(&self) -> Option<&Vec<TimelinePause>> {
+        self.pauses.as_ref()
+    }
+    fn playback_rate(&self) -> Option<f64> {
+        self.playback_rate
+    }
+    fn group_mode(&self) -> GroupMode {
+        self.group_mode.clone().unwrap_or_default()
+    }
+    fn outlives_parent(&self) -> bool {
+        self.outlives_parent
+    }
+    fn class_inheritance(&self) -> ClassInheritance {
+        self.class_inheritance.clone().unwrap_or_default()
+    }
+    fn priority_segments(&self) -> Option<&Vec<TimelinePrioritySegment>> {
+        self.priority_segments.as_ref()
+    }
 }
 
 #[cfg(test)]
@@ -108,6 +185,15 @@ mod tests {
             }],
             children: None,
             keyframes: None,
+            limit_count: None,
+            limit_time: None,
+            transition_duration: None,
+            pauses: None,
+            playback_rate: None,
+            group_mode: None,
+            outlives_parent: false,
+            class_inheritance: None,
+            priority_segments: None,
         };
 
         let j = serde_json::to_string(&src).unwrap();
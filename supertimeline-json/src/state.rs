@@ -0,0 +1,278 @@
+use crate::object::{JsonTimelineObject, JsonTimelineObjectKeyframe};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use supertimeline::{
+    get_state, NextEvent, ResolvedStates, Time, TimelineLayerState, TimelineObjectInstance,
+};
+
+/** Lookup of the objects and keyframes of a timeline, including those inside of groups */
+pub struct JsonTimelineIndex<'a> {
+    objects: HashMap<&'a str, &'a JsonTimelineObject>,
+    keyframes: HashMap<&'a str, &'a JsonTimelineObjectKeyframe>,
+}
+impl<'a> JsonTimelineIndex<'a> {
+    pub fn new(timeline: &'a [JsonTimelineObject]) -> JsonTimelineIndex<'a> {
+        let mut index = JsonTimelineIndex {
+            objects: HashMap::new(),
+            keyframes: HashMap::new(),
+        };
+        for obj in timeline {
+            index.add(obj);
+        }
+        index
+    }
+
+    fn add(&mut self, obj: &'a JsonTimelineObject) {
+        self.objects.insert(&obj.id, obj);
+
+        if let Some(keyframes) = &obj.keyframes {
+            for keyframe in keyframes {
+                self.keyframes.insert(&keyframe.id, keyframe);
+            }
+        }
+        if let Some(children) = &obj.children {
+            for child in children {
+                self.add(child);
+            }
+        }
+    }
+
+    pub fn object(&self, id: &str) -> Option<&'a JsonTimelineObject> {
+        self.objects.get(id).copied()
+    }
+
+    pub fn keyframe(&self, id: &str) -> Option<&'a JsonTimelineObjectKeyframe> {
+        self.keyframes.get(id).copied()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JsonTimelineLayerState {
+    pub object_id: String,
+    pub instance: TimelineObjectInstance,
+    /** The content of the object, with the active keyframes merged in */
+    pub content: Value,
+    /** Ids of the keyframes merged into the content, in the order they were applied */
+    pub keyframe_ids: Vec<String>,
+    /** Set while the object is transitioning from the object it replaced */
+    pub transition: Option<JsonTimelineLayerTransition>,
+    /** How far into its content the object is */
+    pub content_offset: Time,
+    /** How long until the object leaves the layer. None if it never does */
+    pub remaining: Option<Time>,
+    /** True if the object is continuing after being covered by other objects */
+    pub resumed: bool,
+    /** True if the object is paused, so its content_offset is frozen */
+    pub paused: bool,
+    /** The rate the content plays at, from the playback rates of the parent groups */
+    pub playback_rate: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JsonTimelineLayerTransition {
+    /** The object that is replaced, with its content */
+    pub outgoing: Box<JsonTimelineLayerState>,
+    pub start: Time,
+    pub end: Time,
+    pub progress: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JsonTimelineState {
+    pub time: Time,
+    pub layers: HashMap<String, JsonTimelineLayerState>,
+    /** The objects on the multi-slot layers, in the order of the layer */
+    pub slots: HashMap<String, Vec<JsonTimelineLayerState>>,
+    pub next_events: Vec<NextEvent>,
+}
+
+/** Like get_state, but with the content of the keyframes merged into the content of the objects */
+pub fn get_state_with_content(
+    index: &JsonTimelineIndex,
+    resolved: &ResolvedStates,
+    time: Time,
+    event_limit: Option<usize>,
+) -> JsonTimelineState {
+    let state = get_state(resolved, time, event_limit);
+
+    let layers = state
+        .layers
+        .into_iter()
+        .map(|(layer_id, layer)| (layer_id, layer_state_with_content(index, layer)))
+        .collect();
+    let slots = state
+        .slots
+        .into_iter()
+        .map(|(layer_id, layers)| {
+            let layers = layers
+                .into_iter()
+                .map(|layer| layer_state_with_content(index, layer))
+                .collect();
+            (layer_id, layers)
+        })
+        .collect();
+
+    JsonTimelineState {
+        time: state.time,
+        layers,
+        slots,
+        next_events: state.next_events,
+    }
+}
+
+fn layer_state_with_content(
+    index: &JsonTimelineIndex,
+    layer: TimelineLayerState,
+) -> JsonTimelineLayerState {
+    let mut content = index
+        .object(&layer.object_id)
+        .map(|obj| obj.content.clone())
+        .unwrap_or(Value::Null);
+
+    let mut keyframe_ids = Vec::new();
+    for keyframe in &layer.keyframes {
+        if let Some(raw_keyframe) = index.keyframe(&keyframe.info.id) {
+            apply_keyframe_content(&mut content, &raw_keyframe.content);
+            keyframe_ids.push(keyframe.info.id.clone());
+        }
+    }
+
+    let transition = layer
+        .transition
+        .map(|transition| JsonTimelineLayerTransition {
+            outgoing: Box::new(layer_state_with_content(index, *transition.outgoing)),
+            start: transition.start,
+            end: transition.end,
+            progress: transition.progress,
+        });
+
+    JsonTimelineLayerState {
+        object_id: layer.object_id,
+        instance: layer.instance,
+        content,
+        keyframe_ids,
+        transition,
+        content_offset: layer.content_offset,
+        remaining: layer.remaining,
+        resumed: layer.resumed,
+        paused: layer.paused,
+        playback_rate: layer.playback_rate,
+    }
+}
+
+/** Deep-merge the content of a keyframe into the content of an object.
+ * Objects are merged key by key, arrays are merged by index and truncated to the length of the keyframe's array, anything else is replaced
+ */
+pub fn apply_keyframe_content(content: &mut Value, keyframe_content: &Value) {
+    match keyframe_content {
+        Value::Object(keyframe_map) => {
+            if !content.is_object() {
+                *content = Value::Object(serde_json::Map::new());
+            }
+            if let Value::Object(map) = content {
+                for (key, value) in keyframe_map {
+                    apply_keyframe_content(map.entry(key.clone()).or_insert(Value::Null), value);
+                }
+            }
+        }
+        Value::Array(keyframe_arr) => {
+            if !content.is_array() {
+                *content = Value::Array(Vec::new());
+            }
+            if let Value::Array(arr) = content {
+                arr.resize(keyframe_arr.len(), Value::Null);
+                for (target, value) in arr.iter_mut().zip(keyframe_arr) {
+                    apply_keyframe_content(target, value);
+                }
+            }
+        }
+        value => *content = value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use supertimeline::{resolve_all_states, resolve_timeline, ResolveOptions};
+
+    #[test]
+    fn merge_content() {
+        let mut content = json!({
+            "a": 1,
+            "b": { "c": 2, "d": 3 },
+            "e": [1, 2, 3],
+            "f": "text",
+        });
+        apply_keyframe_content(
+            &mut content,
+            &json!({
+                "b": { "d": 4, "g": 5 },
+                "e": [{ "x": 1 }, 9],
+                "f": { "y": true },
+            }),
+        );
+
+        assert_eq!(
+            content,
+            json!({
+                "a": 1,
+                "b": { "c": 2, "d": 4, "g": 5 },
+                "e": [{ "x": 1 }, 9],
+                "f": { "y": true },
+            })
+        );
+    }
+
+    #[test]
+    fn state_with_content() {
+        let timeline: Vec<JsonTimelineObject> = serde_json::from_value(json!([
+            {
+                "id": "video",
+                "layer": "0",
+                "enable": [{ "start": 0, "end": 100 }],
+                "content": { "file": "amb.mp4", "mixer": { "opacity": 1, "volume": 1 } },
+                "keyframes": [
+                    {
+                        "id": "kf0",
+                        "enable": [{ "start": 10 }],
+                        "content": { "mixer": { "opacity": 0.5 } },
+                        "priority": 1,
+                    },
+                    {
+                        "id": "kf1",
+                        "enable": [{ "start": 20 }],
+                        "content": { "mixer": { "opacity": 0, "volume": 0 } },
+                    },
+                ],
+            },
+        ]))
+        .unwrap();
+
+        let options = ResolveOptions {
+            time: 0,
+            limit_count: None,
+            limit_time: None,
+        };
+        let resolved = resolve_timeline(&timeline, options).unwrap();
+        let states = resolve_all_states(&resolved, None).unwrap();
+        let index = JsonTimelineIndex::new(&timeline);
+
+        let state = get_state_with_content(&index, &states, 5, None);
+        let layer = state.layers.get("0").unwrap();
+        assert_eq!(
+            layer.content,
+            json!({ "file": "amb.mp4", "mixer": { "opacity": 1, "volume": 1 } })
+        );
+
+        // kf0 has the higher priority, so is applied last
+        let state = get_state_with_content(&index, &states, 25, None);
+        let layer = state.layers.get("0").unwrap();
+        assert_eq!(layer.keyframe_ids, vec!["kf1", "kf0"]);
+        assert_eq!(
+            layer.content,
+            json!({ "file": "amb.mp4", "mixer": { "opacity": 0.5, "volume": 0 } })
+        );
+    }
+}
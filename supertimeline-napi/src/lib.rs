@@ -64,6 +64,9 @@ fn parse_enable_inner(obj: JsObject) -> Result<TimelineEnable> {
     enable_while: None,
     duration: None,
     repeating: None,
+    repeat_count: None,
+    repeat_until: None,
+    recurrence: None,
   };
 
   if obj.has_named_property("start")? {
@@ -86,6 +89,14 @@ fn parse_enable_inner(obj: JsObject) -> Result<TimelineEnable> {
     let raw_repeating = obj.get_named_property::<JsUnknown>("repeating")?;
     result.repeating = Some(parse_expr(raw_repeating)?);
   }
+  if obj.has_named_property("repeatCount")? {
+    let raw_repeat_count: u32 = obj.get_named_property::<JsNumber>("repeatCount")?.try_into()?;
+    result.repeat_count = Some(raw_repeat_count as usize);
+  }
+  if obj.has_named_property("repeatUntil")? {
+    let raw_repeat_until = obj.get_named_property::<JsUnknown>("repeatUntil")?;
+    result.repeat_until = Some(parse_expr(raw_repeat_until)?);
+  }
 
   Ok(result)
 }
@@ -0,0 +1,159 @@
+use crate::state::{LayerStates, ResolvedStates};
+use crate::util::Time;
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ops::Bound;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct TimeRange {
+    pub start: Time,
+    pub end: Time,
+}
+
+/** A continuous time an instance of an object is on a layer */
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct LayerItem {
+    pub object_id: String,
+    pub start: Time,
+    pub end: Time,
+}
+
+/** How a layer is used within a window of time. Everything is clipped to the window */
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct LayerUsage {
+    /** The ranges where nothing is on the layer */
+    pub gaps: Vec<TimeRange>,
+    /** How much of the window something is on the layer, from 0 to 100 */
+    pub occupied_percent: f64,
+    /** The number of times the objects on the layer change, including to and from being empty */
+    pub switches: usize,
+    /** The item that is on the layer for the shortest time */
+    pub shortest_item: Option<LayerItem>,
+}
+
+impl ResolvedStates {
+    /** Analyze how the layers are used from (inclusive) until (exclusive) the given times */
+    pub fn layer_usage(
+        &self,
+        layers: &[&str],
+        from: Time,
+        to: Time,
+    ) -> HashMap<String, LayerUsage> {
+        layers
+            .iter()
+            .map(|layer| {
+                let usage = if let Some(layer_states) = self.state.get(*layer) {
+                    analyze_layer(layer_states, from, to)
+                } else if let Some(layer_states) = self.slots.get(*layer) {
+                    analyze_layer(layer_states, from, to)
+                } else {
+                    analyze_layer::<Vec<_>>(&BTreeMap::new(), from, to)
+                };
+                (layer.to_string(), usage)
+            })
+            .collect()
+    }
+}
+
+fn analyze_layer<T: LayerStates>(
+    layer_states: &BTreeMap<Time, T>,
+    from: Time,
+    to: Time,
+) -> LayerUsage {
+    let mut usage = LayerUsage {
+        gaps: Vec::new(),
+        occupied_percent: 0.0,
+        switches: 0,
+        shortest_item: None,
+    };
+    if from >= to {
+        return usage;
+    }
+
+    let occupants = |state: &T| -> Vec<(String, String)> {
+        state
+            .as_states()
+            .iter()
+            .map(|state| (state.object_id.clone(), state.instance.id.clone()))
+            .collect()
+    };
+
+    // The objects on the layer at each time they change, ignoring changes of only the keyframes
+    let mut segments: Vec<(Time, Vec<(String, String)>)> = vec![(
+        from,
+        layer_states
+            .range(..=from)
+            .next_back()
+            .map(|(_, state)| occupants(state))
+            .unwrap_or_default(),
+    )];
+    for (time, state) in layer_states.range((Bound::Excluded(from), Bound::Excluded(to))) {
+        let state = occupants(state);
+        if segments
+            .last()
+            .map(|(_, last)| last != &state)
+            .unwrap_or(true)
+        {
+            segments.push((*time, state));
+        }
+    }
+    usage.switches = segments.len() - 1;
+
+    let mut occupied: Time = 0;
+    let mut items = Vec::new();
+    let mut open_items: Vec<(String, String, Time)> = Vec::new();
+    for (index, (start, state)) in segments.iter().enumerate() {
+        let end = segments.get(index + 1).map(|(next, _)| *next).unwrap_or(to);
+        if state.is_empty() {
+            usage.gaps.push(TimeRange { start: *start, end });
+        } else {
+            occupied += end - start;
+        }
+
+        // Close the items that are no longer on the layer
+        let (ended, continued): (Vec<_>, Vec<_>) =
+            open_items
+                .into_iter()
+                .partition(|(object_id, instance_id, _)| {
+                    !state
+                        .iter()
+                        .any(|(o, i)| o == object_id && i == instance_id)
+                });
+        for (object_id, _, item_start) in ended {
+            items.push(LayerItem {
+                object_id,
+                start: item_start,
+                end: *start,
+            });
+        }
+        open_items = continued;
+        for (object_id, instance_id) in state {
+            if !open_items
+                .iter()
+                .any(|(o, i, _)| o == object_id && i == instance_id)
+            {
+                open_items.push((object_id.clone(), instance_id.clone(), *start));
+            }
+        }
+    }
+    items.extend(
+        open_items
+            .into_iter()
+            .map(|(object_id, _, start)| LayerItem {
+                object_id,
+                start,
+                end: to,
+            }),
+    );
+
+    usage.occupied_percent = occupied as f64 * 100.0 / (to - from) as f64;
+    usage.shortest_item = items
+        .into_iter()
+        .min_by_key(|item| (item.end - item.start, item.start));
+    usage
+}
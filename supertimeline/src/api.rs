@@ -1,15 +1,22 @@
+use crate::expression::Expression;
+use crate::expression::{interpret_expression, simplify_expression};
+use crate::expression::{ExpressionObj, ExpressionOperator};
+use crate::instance::ClassInheritance;
+use crate::instance::GroupMode;
+use crate::instance::ResolvedPrioritySegment;
 use crate::instance::TimelineEnable;
 use crate::instance::TimelineObjectInfo;
+use crate::instance::TimelinePause;
+use crate::instance::TimelinePrioritySegment;
 use crate::resolver::ResolveError;
 use crate::resolver::ResolverContext;
-use crate::resolver::{ResolvingTimelineObject, TimelineObjectResolvingStatus};
+use crate::resolver::{ResolvingObjects, ResolvingTimelineObject, TimelineObjectResolvingStatus};
 use crate::state::ResolvedTimelineObject;
 use crate::util::Time;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::rc::Rc;
-use std::sync::RwLock;
+use std::sync::Arc;
 
 pub const DEFAULT_LIMIT_COUNT: usize = 2;
 
@@ -33,14 +40,68 @@ pub trait IsTimelineObject<
     //fn is_group (&self) -> bool;
     fn children(&self) -> Option<&Vec<TChild>>;
     fn priority(&self) -> i64;
+    /** (Optional) Overrides ResolveOptions.limit_count for this object. Inherited by children that don't set their own */
+    fn limit_count(&self) -> Option<usize> {
+        None
+    }
+    /** (Optional) Overrides ResolveOptions.limit_time for this object. Inherited by children that don't set their own */
+    fn limit_time(&self) -> Option<Time> {
+        None
+    }
+    /** (Optional) The duration of the transition when the object replaces another object on its layer.
+     * During the transition, the replaced object is reported in the state as well.
+     * The transition ends early if the replaced object ends before it, and there is none if the replaced object has already ended
+     */
+    fn transition_duration(&self) -> Option<Time> {
+        None
+    }
+    /** (Optional) Intervals where the object is held. A pause that starts while the object plays freezes its content and pushes its end back by the duration of the pause.
+     * A paused instance still ends when the next instance of the object starts. The pauses of a group don't apply to its children
+     */
+    fn pauses(&self) -> Option<&Vec<TimelinePause>> {
+        None
+    }
+    /** (Optional) The rate the children of the group play at, 0.5 being half speed.
+     * The constant times of the children (relative to the start of the group) and their durations are scaled by it
+     */
+    fn playback_rate(&self) -> Option<f64> {
+        None
+    }
+    /** (Optional) How the children of the group are timed, see GroupMode */
+    fn group_mode(&self) -> GroupMode {
+        GroupMode::Parallel
+    }
+    /** (Optional) If true, the object is not ended by the end of its parent group. It still has to start while the parent plays */
+    fn outlives_parent(&self) -> bool {
+        false
+    }
+    /** (Optional) Lets the descendants of the object inherit its classes, so that they are found by class references too */
+    fn class_inheritance(&self) -> ClassInheritance {
+        ClassInheritance::None
+    }
+    /** (Optional) Priorities that apply instead of .priority while their expressions are true, like while `.breaking_news` plays.
+     * When several segments apply at the same time, the last one of them is used
+     */
+    fn priority_segments(&self) -> Option<&Vec<TimelinePrioritySegment>> {
+        None
+    }
 }
 
 pub trait IsTimelineKeyframe {
     fn id(&self) -> &str;
     fn enable(&self) -> &Vec<TimelineEnable>;
-    //fn duration (&self) -> Option<TimelineKeyframeDuration>;
     fn classes(&self) -> Option<&Vec<String>>;
     fn disabled(&self) -> bool;
+    /** (Optional) The duration of the keyframe. Used by the enables that don't set their own end or duration */
+    fn duration(&self) -> Option<&Expression> {
+        None
+    }
+    /** When several keyframes of an object are active, they are applied in order of priority, so the one with the highest priority wins.
+     * Keyframes with the same priority are applied in the order they started
+     */
+    fn priority(&self) -> i64 {
+        0
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -59,44 +120,144 @@ pub struct ResolveOptions {
     //                                        // cache?: ResolverCache
 }
 
-fn add_object_to_resolved_timeline<
-    TChild: IsTimelineObject<TChild, TKeyframe>,
-    TKeyframe: IsTimelineKeyframe,
->(
+fn add_object_to_resolved_timeline(
     timeline: &mut ResolvedTimeline,
-    resolving_objects: &mut HashMap<String, ResolvingTimelineObject>,
+    resolving_objects: &mut ResolvingObjects,
     obj: ResolvingTimelineObject,
-    raw_obj: Option<&TChild>,
+    classes: Option<&Vec<String>>,
 ) {
-    let obj_id = obj.info.id.to_string();
-
-    if let Some(raw_obj) = raw_obj {
-        if let Some(classes) = raw_obj.classes() {
-            for class in classes {
-                if let Some(existing) = timeline.classes.get_mut(class) {
-                    existing.push(obj_id.clone());
-                } else {
-                    timeline
-                        .classes
-                        .insert(class.to_string(), vec![obj_id.clone()]);
-                }
-            }
-        }
+    let obj_id = &obj.info.id;
 
-        let obj_layer = raw_obj.layer();
-        if !obj_layer.is_empty() {
-            if let Some(existing) = timeline.layers.get_mut(obj_layer) {
+    if let Some(classes) = classes {
+        for class in classes {
+            if let Some(existing) = timeline.classes.get_mut(class) {
                 existing.push(obj_id.clone());
             } else {
                 timeline
-                    .layers
-                    .insert(obj_layer.to_string(), vec![obj_id.clone()]);
+                    .classes
+                    .insert(class.to_string(), vec![obj_id.clone()]);
             }
         }
     }
+    for class in &obj.info.inherited_classes {
+        timeline
+            .classes
+            .entry(class.clone())
+            .or_default()
+            .push(obj_id.clone());
+        timeline
+            .inherited_classes
+            .entry(class.clone())
+            .or_default()
+            .push(obj_id.clone());
+    }
+
+    let obj_layer = &obj.info.layer;
+    if !obj_layer.is_empty() {
+        if let Some(existing) = timeline.layers.get_mut(obj_layer) {
+            existing.push(obj_id.clone());
+        } else {
+            timeline
+                .layers
+                .insert(obj_layer.to_string(), vec![obj_id.clone()]);
+        }
+    }
 
     // finally move the object
-    resolving_objects.insert(obj_id, obj);
+    resolving_objects.insert(obj);
+}
+
+/** The enable of a keyframe, with the duration of the keyframe applied */
+fn keyframe_enable<TKeyframe: IsTimelineKeyframe>(keyframe: &TKeyframe) -> Vec<TimelineEnable> {
+    let mut enable = keyframe.enable().clone();
+    if let Some(duration) = keyframe.duration() {
+        for enable in &mut enable {
+            if enable.enable_end.is_none()
+                && enable.enable_while.is_none()
+                && enable.duration.is_none()
+            {
+                enable.duration = Some(duration.clone());
+            }
+        }
+    }
+    enable
+}
+
+/** The enable of a child of a sequential group, chained after the previous child */
+fn sequential_enable(enable: &[TimelineEnable], previous_id: Option<&str>) -> Vec<TimelineEnable> {
+    let mut enable = enable.to_vec();
+    if enable.is_empty() {
+        enable.push(TimelineEnable::default());
+    }
+    for enable in &mut enable {
+        if enable.enable_while.is_some() {
+            continue;
+        }
+        let offset = match &enable.enable_start {
+            None => 0,
+            Some(start) => {
+                match interpret_expression(start).and_then(|e| simplify_expression(&e)) {
+                    Ok(Expression::Number(offset)) => offset,
+                    // An expression with references is timed on its own
+                    _ => continue,
+                }
+            }
+        };
+        // The start is no longer constant, so a constant end would be read as an absolute time
+        if enable.duration.is_none() {
+            if let Some(Ok(Expression::Number(end))) = enable
+                .enable_end
+                .as_ref()
+                .map(|end| interpret_expression(end).and_then(|e| simplify_expression(&e)))
+            {
+                enable.enable_end = None;
+                enable.duration = Some(Expression::Number((end - offset).max(0)));
+            }
+        }
+        enable.enable_start = Some(match previous_id {
+            // The first child is offset from the start of the group
+            None => Expression::Number(offset),
+            Some(previous_id) => {
+                let previous_end = Expression::String(format!("#{}.end", previous_id));
+                if offset < 0 {
+                    ExpressionObj::create(
+                        previous_end,
+                        ExpressionOperator::Subtract,
+                        Expression::Number(-offset),
+                    )
+                } else {
+                    ExpressionObj::create(
+                        previous_end,
+                        ExpressionOperator::Add,
+                        Expression::Number(offset),
+                    )
+                }
+            }
+        });
+    }
+    enable
+}
+
+fn sorted_pauses(pauses: Option<&Vec<TimelinePause>>) -> Vec<TimelinePause> {
+    let mut pauses = pauses.cloned().unwrap_or_default();
+    pauses.sort_by_key(|pause| pause.start);
+    pauses
+}
+
+/** The classes that are passed on from the parents of an object */
+#[derive(Default)]
+struct InheritedClasses {
+    objects: Vec<String>,
+    keyframes: Vec<String>,
+}
+
+/** The classes that are inherited, leaving out the ones that are declared anyway */
+fn undeclared_classes(inherited: &[String], declared: Option<&Vec<String>>) -> Vec<String> {
+    inherited
+        .iter()
+        .filter(|class| !declared.is_some_and(|declared| declared.contains(class)))
+        .cloned()
+        .collect()
 }
 
 fn add_object_to_timeline<
@@ -104,39 +265,80 @@ fn add_object_to_timeline<
     TKeyframe: IsTimelineKeyframe,
 >(
     timeline: &mut ResolvedTimeline,
-    resolving_objects: &mut HashMap<String, ResolvingTimelineObject>,
+    resolving_objects: &mut ResolvingObjects,
     obj: &TChild,
     depth: usize,
-    parent_id: Option<&String>,
+    parent: Option<&TimelineObjectInfo>,
+    previous_sibling: Option<&str>,
+    inherited: &InheritedClasses,
 ) {
     // TODO - duplicate id check
     // if (resolvedTimeline.objects[obj.id]) throw Error(`All timelineObjects must be unique! (duplicate: "${obj.id}")`)
 
+    let parent_playback_rate = parent.map_or(1.0, |p| p.playback_rate);
+    let enable = match parent.map(|p| &p.group_mode) {
+        Some(GroupMode::Sequential) => sequential_enable(obj.enable(), previous_sibling),
+        _ => obj.enable().clone(),
+    };
+
     let resolved_obj = ResolvingTimelineObject {
-        resolved: RwLock::new(TimelineObjectResolvingStatus::Pending),
-        info: TimelineObjectInfo {
+        resolved: TimelineObjectResolvingStatus::Pending,
+        info: Arc::new(TimelineObjectInfo {
             id: obj.id().to_string(),
-            enable: obj.enable().clone(),
+            enable,
             priority: obj.priority(),
             disabled: obj.disabled(),
             layer: obj.layer().to_string(),
 
             depth,
-            parent_id: parent_id.cloned(),
+            parent_id: parent.map(|p| p.id.clone()),
             is_keyframe: false,
-        },
+            limit_count: obj.limit_count().or(parent.and_then(|p| p.limit_count)),
+            limit_time: obj.limit_time().or(parent.and_then(|p| p.limit_time)),
+            transition_duration: obj.transition_duration(),
+            pauses: sorted_pauses(obj.pauses()),
+            playback_rate: parent_playback_rate * obj.playback_rate().unwrap_or(1.0),
+            parent_playback_rate,
+            group_mode: obj.group_mode(),
+            outlives_parent: obj.outlives_parent(),
+            inherited_classes: undeclared_classes(&inherited.objects, obj.classes()),
+            priority_segments: obj.priority_segments().cloned().unwrap_or_default(),
+        }),
     };
 
+    let mut passed_on = InheritedClasses {
+        objects: inherited.objects.clone(),
+        keyframes: inherited.keyframes.clone(),
+    };
+    let inheritance = obj.class_inheritance();
+    for class in obj.classes().into_iter().flatten() {
+        if inheritance != ClassInheritance::None && !passed_on.objects.contains(class) {
+            passed_on.objects.push(class.clone());
+        }
+        if inheritance == ClassInheritance::ChildrenAndKeyframes
+            && !passed_on.keyframes.contains(class)
+        {
+            passed_on.keyframes.push(class.clone());
+        }
+    }
+
     // track child objects
     if let Some(children) = obj.children() {
+        // Disabled children are left out of the chain of a sequential group
+        let mut previous_sibling = None;
         for child in children {
             add_object_to_timeline(
                 timeline,
                 resolving_objects,
                 child,
                 depth + 1,
-                Some(&resolved_obj.info.id),
+                Some(&resolved_obj.info),
+                previous_sibling,
+                &passed_on,
             );
+            if !child.disabled() {
+                previous_sibling = Some(child.id());
+            }
         }
     }
 
@@ -144,29 +346,39 @@ fn add_object_to_timeline<
     if let Some(keyframes) = obj.keyframes() {
         for keyframe in keyframes {
             let resolved_obj = ResolvingTimelineObject {
-                resolved: RwLock::new(TimelineObjectResolvingStatus::Pending),
-                info: TimelineObjectInfo {
+                resolved: TimelineObjectResolvingStatus::Pending,
+                info: Arc::new(TimelineObjectInfo {
                     id: keyframe.id().to_string(),
-                    enable: keyframe.enable().clone(),
-                    priority: 0,           // not supported
-                    layer: "".to_string(), // not supported
+                    enable: keyframe_enable(keyframe),
+                    priority: keyframe.priority(),
+                    layer: "".to_string(), // keyframes are applied on the layer of their parent
                     disabled: keyframe.disabled(),
 
                     depth: depth + 1,
                     parent_id: Some(resolved_obj.info.id.clone()),
                     is_keyframe: true,
-                },
+                    limit_count: resolved_obj.info.limit_count,
+                    limit_time: resolved_obj.info.limit_time,
+                    transition_duration: None,
+                    pauses: Vec::new(),
+                    playback_rate: resolved_obj.info.playback_rate,
+                    parent_playback_rate: resolved_obj.info.playback_rate,
+                    group_mode: GroupMode::Parallel,
+                    outlives_parent: false,
+                    inherited_classes: undeclared_classes(&passed_on.keyframes, keyframe.classes()),
+                    priority_segments: Vec::new(),
+                }),
             };
-            add_object_to_resolved_timeline::<TChild, TKeyframe>(
+            add_object_to_resolved_timeline(
                 timeline,
                 resolving_objects,
                 resolved_obj,
-                None,
+                keyframe.classes(),
             )
         }
     }
 
-    add_object_to_resolved_timeline(timeline, resolving_objects, resolved_obj, Some(obj));
+    add_object_to_resolved_timeline(timeline, resolving_objects, resolved_obj, obj.classes());
 }
 
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
@@ -176,8 +388,14 @@ pub struct ResolvedTimeline {
     pub objects: HashMap<String, ResolvedTimelineObject>,
     /** Map of all classes on timeline, maps className to object ids */
     pub classes: HashMap<String, Vec<String>>,
+    /** The objects in classes that are only in the class because they inherit it, maps className to object ids */
+    pub inherited_classes: HashMap<String, Vec<String>>,
     /** Map of the object ids, per layer */
     pub layers: HashMap<String, Vec<String>>,
+    /** The earliest time where a repeating object was cut off by limit_count or limit_time.
+     * After this time the result may be incomplete and the timeline should be resolved again. None if nothing was cut off
+     */
+    pub valid_until: Option<Time>,
 }
 
 pub fn resolve_timeline<
@@ -186,46 +404,67 @@ pub fn resolve_timeline<
 >(
     timeline: &[TChild],
     options: ResolveOptions,
+) -> Result<Box<ResolvedTimeline>, ResolveError> {
+    resolve_timeline_from_id(timeline, options, 0)
+}
+
+fn resolve_timeline_from_id<
+    TChild: IsTimelineObject<TChild, TKeyframe>,
+    TKeyframe: IsTimelineKeyframe,
+>(
+    timeline: &[TChild],
+    options: ResolveOptions,
+    next_id: usize,
 ) -> Result<Box<ResolvedTimeline>, ResolveError> {
     let mut resolved_timeline = Box::new(ResolvedTimeline {
         objects: HashMap::new(),
         classes: HashMap::new(),
+        inherited_classes: HashMap::new(),
         layers: HashMap::new(),
         options,
+        valid_until: None,
     });
 
     // Step 1: pre-populate resolvedTimeline with objects
-    let mut resolving_objects = HashMap::new();
+    let mut resolving_objects = ResolvingObjects::new();
     for obj in timeline {
-        add_object_to_timeline(&mut resolved_timeline, &mut resolving_objects, obj, 0, None);
+        add_object_to_timeline(
+            &mut resolved_timeline,
+            &mut resolving_objects,
+            obj,
+            0,
+            None,
+            None,
+            &InheritedClasses::default(),
+        );
     }
 
-    let resolver_context = ResolverContext::create(&resolved_timeline, resolving_objects);
+    let mut resolver_context = ResolverContext::create(&resolved_timeline, resolving_objects);
+    resolver_context.continue_ids_from(next_id);
 
     // Step 2: go though and resolve the objects
     // TODO - support cache
-    for obj in resolver_context.objects.iter() {
-        // TODO - the immutability here will cause me nightmares
-        resolver_context.resolve_object(obj.1)?;
+    for index in 0..resolver_context.objects.len() {
+        resolver_context.resolve_object(index)?;
     }
 
+    let valid_until = resolver_context.valid_until();
     let mut unresolved_ids = Vec::new();
 
     // convert the objects/instances, and verify everything resolved
-    for (id, obj) in resolver_context.objects.into_iter() {
-        let inner = obj.resolved.into_inner().unwrap(); // TODO - handle error
-        match inner {
+    for obj in resolver_context.objects.into_iter() {
+        match obj.resolved {
             TimelineObjectResolvingStatus::Pending => {
-                unresolved_ids.push(id);
+                unresolved_ids.push(obj.info.id.clone());
             }
             TimelineObjectResolvingStatus::InProgress(_) => {
-                unresolved_ids.push(id);
+                unresolved_ids.push(obj.info.id.clone());
             }
             TimelineObjectResolvingStatus::Complete(res) => {
                 resolved_timeline.objects.insert(
-                    id,
+                    obj.info.id.clone(),
                     ResolvedTimelineObject {
-                        info: Rc::new(obj.info),
+                        info: obj.info,
                         resolved: res,
                     },
                 );
@@ -236,9 +475,89 @@ pub fn resolve_timeline<
     if !unresolved_ids.is_empty() {
         Err(ResolveError::UnresolvedObjects(unresolved_ids))
     } else {
+        resolved_timeline.valid_until = valid_until;
         Ok(resolved_timeline)
     }
 }
 
+/** Extends a previous result past the time where it stops being valid.
+ * The timeline is resolved again from valid_until, with the same limits and limit_time moved forward as much as the time is.
+ * The instances starting before valid_until are kept from the previous result, the ones after it come from the new resolve.
+ * Returns None if the previous result is complete.
+ */
+pub fn extend_resolved_timeline<
+    TChild: IsTimelineObject<TChild, TKeyframe>,
+    TKeyframe: IsTimelineKeyframe,
+>(
+    timeline: &[TChild],
+    previous: &ResolvedTimeline,
+) -> Result<Option<Box<ResolvedTimeline>>, ResolveError> {
+    if let Some(valid_until) = previous.valid_until {
+        let shift = valid_until.saturating_sub(previous.options.time);
+        let options = ResolveOptions {
+            time: valid_until,
+            limit_count: previous.options.limit_count,
+            limit_time: previous.options.limit_time.map(|t| t + shift),
+        };
+        let next_id = previous
+            .objects
+            .values()
+            .flat_map(|obj| obj.resolved.instances.iter())
+            .filter_map(|instance| instance.id.rsplit('@').next()?.parse::<usize>().ok())
+            .max()
+            .map_or(0, |id| id + 1);
+        let mut extended = resolve_timeline_from_id(timeline, options, next_id)?;
+
+        for (id, obj) in extended.objects.iter_mut() {
+            if let Some(previous_obj) = previous.objects.get(id) {
+                let resolved = &mut obj.resolved;
+
+                let mut instances = previous_obj
+                    .resolved
+                    .instances
+                    .iter()
+                    .filter(|instance| instance.start < valid_until)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                instances.extend(
+                    resolved
+                        .instances
+                        .drain(..)
+                        .filter(|instance| instance.start >= valid_until),
+                );
+                resolved.instances = instances;
+
+                // Later segments take precedence, so the segments are split at valid_until to keep their order on each side
+                let mut priority_segments = previous_obj
+                    .resolved
+                    .priority_segments
+                    .iter()
+                    .filter(|segment| segment.start < valid_until)
+                    .map(|segment| ResolvedPrioritySegment {
+                        end: Some(segment.end.map_or(valid_until, |end| end.min(valid_until))),
+                        ..segment.clone()
+                    })
+                    .collect::<Vec<_>>();
+                priority_segments.extend(
+                    resolved
+                        .priority_segments
+                        .drain(..)
+                        .filter(|segment| segment.end.is_none_or(|end| end > valid_until))
+                        .map(|segment| ResolvedPrioritySegment {
+                            start: segment.start.max(valid_until),
+                            ..segment
+                        }),
+                );
+                resolved.priority_segments = priority_segments;
+            }
+        }
+        extended.options.time = previous.options.time;
+
+        Ok(Some(extended))
+    } else {
+        Ok(None)
+    }
+}
+
 #[cfg(test)]
 mod test {}
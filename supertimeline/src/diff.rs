@@ -0,0 +1,271 @@
+use crate::api::ResolvedTimeline;
+use crate::instance::TimelineObjectInstance;
+use crate::state::{TimelineLayerState, TimelineState};
+use crate::util::Time;
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/** How the state of a layer differs between two TimelineStates */
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum LayerStateDiff {
+    /** Something started playing on the layer */
+    Added(TimelineLayerState),
+    /** The layer became empty */
+    Removed(TimelineLayerState),
+    /** Another object, or another instance of the same object, is playing on the layer */
+    Replaced {
+        previous: TimelineLayerState,
+        current: TimelineLayerState,
+    },
+    /** The same instance is playing, but with other keyframes applied */
+    KeyframesChanged {
+        previous: TimelineLayerState,
+        current: TimelineLayerState,
+        /** Ids of the keyframes that are only applied in the current state */
+        keyframes_added: Vec<String>,
+        /** Ids of the keyframes that are only applied in the previous state */
+        keyframes_removed: Vec<String>,
+    },
+}
+
+/** The differences between two TimelineStates. Layers that are unchanged are left out */
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct TimelineStateDiff {
+    pub layers: HashMap<String, LayerStateDiff>,
+    /** The differences of the multi-slot layers, one per object that differs */
+    pub slots: HashMap<String, Vec<LayerStateDiff>>,
+}
+impl TimelineStateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty() && self.slots.is_empty()
+    }
+}
+
+/** How an instance of an object differs between two ResolvedTimelines */
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum InstanceDiff {
+    /** The instance only exists in the current timeline */
+    Added(TimelineObjectInstance),
+    /** The instance only exists in the previous timeline */
+    Removed(TimelineObjectInstance),
+    /** The instance exists in both timelines, but the start or end has changed */
+    Moved {
+        previous: TimelineObjectInstance,
+        current: TimelineObjectInstance,
+    },
+}
+
+/** The differences between two ResolvedTimelines. Objects where the instances are unchanged are left out */
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct ResolvedTimelineDiff {
+    /** The changed instances, per object id. Ordered by the start of the instances */
+    pub objects: HashMap<String, Vec<InstanceDiff>>,
+}
+impl ResolvedTimelineDiff {
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+/** Compare what is playing on each layer in two states.
+ * The instances are compared by the object and the start time, as the ids of instances are not stable between resolves
+ */
+pub fn diff_states(previous: &TimelineState, current: &TimelineState) -> TimelineStateDiff {
+    let mut diff = TimelineStateDiff::default();
+
+    let layer_ids: HashSet<&String> = previous
+        .layers
+        .keys()
+        .chain(current.layers.keys())
+        .collect();
+    for layer_id in layer_ids {
+        let layer_diff = match (previous.layers.get(layer_id), current.layers.get(layer_id)) {
+            (None, None) => None,
+            (None, Some(current)) => Some(LayerStateDiff::Added(current.clone())),
+            (Some(previous), None) => Some(LayerStateDiff::Removed(previous.clone())),
+            (Some(previous), Some(current)) => diff_layer_state(previous, current),
+        };
+        if let Some(layer_diff) = layer_diff {
+            diff.layers.insert(layer_id.clone(), layer_diff);
+        }
+    }
+
+    let layer_ids: HashSet<&String> = previous.slots.keys().chain(current.slots.keys()).collect();
+    for layer_id in layer_ids {
+        let states = |state: &TimelineState| -> Vec<TimelineLayerState> {
+            state.slots.get(layer_id).cloned().unwrap_or_default()
+        };
+        let slot_diffs = diff_slots(states(previous), states(current));
+        if !slot_diffs.is_empty() {
+            diff.slots.insert(layer_id.clone(), slot_diffs);
+        }
+    }
+
+    diff
+}
+
+/** Objects that are on the layer in both states are compared with each other, the rest are paired up in order as replacements */
+fn diff_slots(
+    previous: Vec<TimelineLayerState>,
+    current: Vec<TimelineLayerState>,
+) -> Vec<LayerStateDiff> {
+    let is_same = |a: &TimelineLayerState, b: &TimelineLayerState| {
+        a.object_id == b.object_id && a.instance.start == b.instance.start
+    };
+    let mut diffs = Vec::new();
+
+    for state in &current {
+        if let Some(previous_state) = previous.iter().find(|p| is_same(p, state)) {
+            diffs.extend(diff_layer_state(previous_state, state));
+        }
+    }
+
+    let mut unmatched_previous = previous
+        .iter()
+        .filter(|p| !current.iter().any(|c| is_same(p, c)));
+    let mut unmatched_current = current
+        .iter()
+        .filter(|c| !previous.iter().any(|p| is_same(p, c)));
+    loop {
+        match (unmatched_previous.next(), unmatched_current.next()) {
+            (None, None) => break,
+            (None, Some(current)) => diffs.push(LayerStateDiff::Added(current.clone())),
+            (Some(previous), None) => diffs.push(LayerStateDiff::Removed(previous.clone())),
+            (Some(previous), Some(current)) => diffs.extend(diff_layer_state(previous, current)),
+        }
+    }
+
+    diffs
+}
+
+fn diff_layer_state(
+    previous: &TimelineLayerState,
+    current: &TimelineLayerState,
+) -> Option<LayerStateDiff> {
+    if previous.object_id != current.object_id || previous.instance.start != current.instance.start
+    {
+        return Some(LayerStateDiff::Replaced {
+            previous: previous.clone(),
+            current: current.clone(),
+        });
+    }
+
+    let keyframe_ids = |state: &TimelineLayerState| -> Vec<String> {
+        state
+            .keyframes
+            .iter()
+            .map(|keyframe| keyframe.info.id.clone())
+            .collect()
+    };
+    let previous_keyframes = keyframe_ids(previous);
+    let current_keyframes = keyframe_ids(current);
+
+    // The order matters too, as that is the order the keyframes are applied in
+    if previous_keyframes != current_keyframes {
+        Some(LayerStateDiff::KeyframesChanged {
+            previous: previous.clone(),
+            current: current.clone(),
+            keyframes_added: current_keyframes
+                .iter()
+                .filter(|id| !previous_keyframes.contains(id))
+                .cloned()
+                .collect(),
+            keyframes_removed: previous_keyframes
+                .iter()
+                .filter(|id| !current_keyframes.contains(id))
+                .cloned()
+                .collect(),
+        })
+    } else {
+        None
+    }
+}
+
+/** Compare the instances of the objects in two resolved timelines.
+ * Instances are matched by time rather than id: first the ones with the same start and end, then the ones with the same start or end, and lastly the ones that overlap
+ */
+pub fn diff_resolved_timelines(
+    previous: &ResolvedTimeline,
+    current: &ResolvedTimeline,
+) -> ResolvedTimelineDiff {
+    let mut diff = ResolvedTimelineDiff::default();
+
+    let object_ids: HashSet<&String> = previous
+        .objects
+        .keys()
+        .chain(current.objects.keys())
+        .collect();
+    for object_id in object_ids {
+        let instances = |timeline: &ResolvedTimeline| -> Vec<TimelineObjectInstance> {
+            timeline
+                .objects
+                .get(object_id)
+                .map(|obj| obj.resolved.instances.clone())
+                .unwrap_or_default()
+        };
+        let instance_diffs = diff_instances(instances(previous), instances(current));
+        if !instance_diffs.is_empty() {
+            diff.objects.insert(object_id.clone(), instance_diffs);
+        }
+    }
+
+    diff
+}
+
+fn diff_instances(
+    previous: Vec<TimelineObjectInstance>,
+    current: Vec<TimelineObjectInstance>,
+) -> Vec<InstanceDiff> {
+    let mut previous: Vec<Option<TimelineObjectInstance>> =
+        previous.into_iter().map(Some).collect();
+    let mut current: Vec<Option<TimelineObjectInstance>> = current.into_iter().map(Some).collect();
+    let mut diffs = Vec::new();
+
+    let matchers: [fn(&TimelineObjectInstance, &TimelineObjectInstance) -> bool; 4] = [
+        |a, b| a.start == b.start && a.end == b.end,
+        |a, b| a.start == b.start,
+        |a, b| a.end == b.end,
+        |a, b| overlaps(a, b),
+    ];
+    for matcher in matchers.iter() {
+        for previous_slot in previous.iter_mut() {
+            let matching = previous_slot.as_ref().and_then(|previous_instance| {
+                current.iter_mut().find(|current_slot| {
+                    current_slot.as_ref().is_some_and(|current_instance| {
+                        matcher(previous_instance, current_instance)
+                    })
+                })
+            });
+            if let Some(current_slot) = matching {
+                let previous_instance = previous_slot.take().unwrap();
+                let current_instance = current_slot.take().unwrap();
+                if previous_instance.start != current_instance.start
+                    || previous_instance.end != current_instance.end
+                {
+                    diffs.push(InstanceDiff::Moved {
+                        previous: previous_instance,
+                        current: current_instance,
+                    });
+                }
+            }
+        }
+    }
+
+    diffs.extend(previous.into_iter().flatten().map(InstanceDiff::Removed));
+    diffs.extend(current.into_iter().flatten().map(InstanceDiff::Added));
+    diffs.sort_by_key(|diff| match diff {
+        InstanceDiff::Added(instance) | InstanceDiff::Removed(instance) => instance.start,
+        InstanceDiff::Moved { previous, current } => previous.start.min(current.start),
+    });
+    diffs
+}
+
+fn overlaps(a: &TimelineObjectInstance, b: &TimelineObjectInstance) -> bool {
+    a.start < b.end.unwrap_or(Time::MAX) && b.start < a.end.unwrap_or(Time::MAX)
+}
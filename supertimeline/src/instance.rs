@@ -1,5 +1,6 @@
 use crate::caps::Cap;
 use crate::expression::Expression;
+use crate::recurrence::TimelineRecurrence;
 use crate::util::Time;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
@@ -20,6 +21,26 @@ pub struct TimelineObjectInfo {
     pub parent_id: Option<String>,
     /** True if object is a keyframe */
     pub is_keyframe: bool,
+    /** Overrides ResolveOptions.limit_count for the object, inherited from the parent if not set */
+    pub limit_count: Option<usize>,
+    /** Overrides ResolveOptions.limit_time for the object, inherited from the parent if not set */
+    pub limit_time: Option<Time>,
+    /** The duration of the transition when the object replaces another object on its layer */
+    pub transition_duration: Option<Time>,
+    /** The times the object is paused, ordered by start */
+    pub pauses: Vec<TimelinePause>,
+    /** The rate the children and keyframes of the object play at, including the playback rates of its parents */
+    pub playback_rate: f64,
+    /** The rate the object plays at, from the playback rates of its parents. Its constant times are scaled by this */
+    pub parent_playback_rate: f64,
+    /** How the children of the object are timed */
+    pub group_mode: GroupMode,
+    /** True if the object may keep playing after its parent has ended */
+    pub outlives_parent: bool,
+    /** The classes the object is in because it inherits them from its parents, see IsTimelineObject.class_inheritance */
+    pub inherited_classes: Vec<String>,
+    /** Priorities that override .priority while they apply, see IsTimelineObject.priority_segments */
+    pub priority_segments: Vec<TimelinePrioritySegment>,
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +60,8 @@ pub struct TimelineObjectResolved {
     pub instances: Vec<TimelineObjectInstance>,
     /** Ids of all other objects that directly affects this object (ie through direct reference, classes, etc) */
     pub direct_references: HashSet<String>,
+    /** The times the priority segments of the object apply, in the order of the segments */
+    pub priority_segments: Vec<ResolvedPrioritySegment>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -71,6 +94,67 @@ pub struct TimelineObjectInstance {
     pub from_instance_id: Option<String>,
 }
 
+/** How the children of a group are timed */
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum GroupMode {
+    /** The children are timed independently of each other, relative to the start of the group */
+    #[default]
+    Parallel,
+    /** The children play one after another, in the order they are in.
+     * A child without a start starts when the previous child ends, and a constant start is an offset from that (negative to overlap).
+     * A constant end is relative to the same point as the start, so it ends the child that long after it would start without an offset
+     */
+    Sequential,
+    /** Only one child plays at a time, and a child that starts ends the one that was playing.
+     * Which child wins follows the same rules as the objects on a layer. A child that is ended never resumes, but one that
+     * can't start because a child with a higher priority is playing starts when that one ends.
+     * Unlike with layers, references to the children see the times they play for. The children themselves see each other's times from before they were ended
+     */
+    Exclusive,
+}
+
+/** A priority that applies while an expression is true, like the .while of an enable */
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelinePrioritySegment {
+    #[cfg_attr(feature = "serde_support", serde(rename = "while"))]
+    pub enable_while: Expression,
+    pub priority: i64,
+}
+
+/** A time where a priority segment applies */
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct ResolvedPrioritySegment {
+    pub start: Time,
+    pub end: Option<Time>,
+    pub priority: i64,
+}
+
+/** Which of the descendants of an object inherit its classes */
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum ClassInheritance {
+    /** The classes are not inherited */
+    #[default]
+    None,
+    /** The children, and their children, inherit the classes */
+    Children,
+    /** Like Children, but the keyframes of the object and its descendants inherit the classes as well */
+    ChildrenAndKeyframes,
+}
+
+/** An interval where an object is held: its content is frozen and its end is pushed back by the duration */
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelinePause {
+    /** The (absolute) time the object is paused at */
+    pub start: Time,
+    /** How long the object is paused for */
+    pub duration: Time,
+}
+
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct TimelineEnable {
@@ -108,4 +192,25 @@ pub struct TimelineEnable {
         serde(skip_serializing_if = "Option::is_none")
     )]
     pub repeating: Option<Expression>,
+
+    /** (Optional) Limits the number of times the object repeats, counted from the first repeat. Only used together with .repeating */
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(rename = "repeatCount", skip_serializing_if = "Option::is_none")
+    )]
+    pub repeat_count: Option<usize>,
+
+    /** (Optional) Stops the repeating at the given time, eg "#show.end". A repeat in progress at that time is ended. Only used together with .repeating */
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(rename = "repeatUntil", skip_serializing_if = "Option::is_none")
+    )]
+    pub repeat_until: Option<Expression>,
+
+    /** (Optional) Makes the object recur according to a calendar rule, instead of with a fixed interval. Overrides .repeating */
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(skip_serializing_if = "Option::is_none")
+    )]
+    pub recurrence: Option<TimelineRecurrence>,
 }
@@ -1,11 +1,14 @@
 mod macros;
 
+mod analysis;
 mod api;
 mod caps;
+mod diff;
 mod events;
 mod expression;
 mod instance;
 mod lookup_expression;
+mod recurrence;
 mod references;
 mod resolver;
 mod state;
@@ -13,16 +16,30 @@ mod util;
 
 //use crate::types::{Expression, ExpressionObj};
 
-pub use api::{resolve_timeline, IsTimelineKeyframe, IsTimelineObject, ResolveOptions};
+pub use analysis::{LayerItem, LayerUsage, TimeRange};
+pub use api::{
+    extend_resolved_timeline, resolve_timeline, IsTimelineKeyframe, IsTimelineObject,
+    ResolveOptions, ResolvedTimeline,
+};
+pub use caps::Cap;
+pub use diff::{
+    diff_resolved_timelines, diff_states, InstanceDiff, LayerStateDiff, ResolvedTimelineDiff,
+    TimelineStateDiff,
+};
 pub use expression::{Expression, ExpressionError, ExpressionObj, ExpressionOperator};
-pub use instance::{TimelineEnable, TimelineObjectInstance};
+pub use instance::{
+    ClassInheritance, GroupMode, ResolvedPrioritySegment, TimelineEnable, TimelineObjectInstance,
+    TimelinePause, TimelinePrioritySegment,
+};
+pub use recurrence::{RecurrenceError, TimelineRecurrence};
+pub use state::TimelineLayerState;
 pub use state::{
-    get_state, resolve_all_states, EventType, NextEvent, ResolvedStates, ResolvedStatesError,
-    ResolvedTimelineObject, ResolvedTimelineObjectInstance, TimelineState,
+    get_state, resolve_all_states, resolve_all_states_with_slots, ConflictReason, EventType,
+    LayerConflict, LayerSlots, LayerTransition, LookaheadObject, NextEvent, ResolvedStates,
+    ResolvedStatesError, ResolvedTimelineObject, ResolvedTimelineObjectInstance,
+    ResolvedTimelineObjectInstanceKeyframe, ShadowedObject, SlotOrder, StateChange, TimelineState,
 };
 pub use util::Time;
-pub use caps::Cap;
-pub use state::TimelineLayerState;
 
 #[cfg(test)]
 mod tests {
@@ -6,7 +6,7 @@ use crate::references::ReferencesBuilder;
 use crate::resolver::ResolveError;
 use crate::resolver::ResolverContext;
 use crate::resolver::{
-    ObjectRefType, ResolvingTimelineObject, TimeWithReference, TimelineObjectResolvingStatus,
+    ObjectIndex, ObjectRefType, TimeWithReference, TimelineObjectResolvingStatus,
 };
 use crate::util::{clean_instances, invert_instances, operate_on_arrays, Time};
 use regex::Regex;
@@ -39,8 +39,8 @@ impl LookupExpressionResult {
 }
 
 pub fn lookup_expression(
-    ctx: &ResolverContext,
-    obj: &ResolvingTimelineObject,
+    ctx: &mut ResolverContext,
+    obj: ObjectIndex,
     expr: &Expression,
     default_ref_type: &ObjectRefType,
 ) -> Result<LookupExpressionResult, ResolveError> {
@@ -77,9 +77,9 @@ pub fn lookup_expression(
             let inner_res = lookup_expression(ctx, obj, inner_expr, default_ref_type)?;
 
             let inner_res2 = match inner_res.result {
-                LookupExpressionResultType::Null => {
-                    LookupExpressionResultType::Instances(invert_instances(ctx, &[]))
-                }
+                LookupExpressionResultType::Null => LookupExpressionResultType::Instances(
+                    invert_instances::<TimelineObjectInstance>(ctx, &[]),
+                ),
                 LookupExpressionResultType::TimeRef(time_ref) => {
                     LookupExpressionResultType::TimeRef(time_ref)
                 } // Can't invert a time
@@ -141,42 +141,28 @@ fn match_expression_references(
 }
 
 fn lookup_expression_str(
-    ctx: &ResolverContext,
-    obj: &ResolvingTimelineObject,
+    ctx: &mut ResolverContext,
+    obj: ObjectIndex,
     expr_str: &str,
     default_ref_type: &ObjectRefType,
 ) -> Result<LookupExpressionResult, ResolveError> {
     // Note: bool expressions are 'parsed' elsewhere
 
     if let Some(expression_references) = match_expression_references(ctx, expr_str) {
-        let mut referenced_objs: Vec<&ResolvingTimelineObject> = Vec::new();
+        let mut referenced_objs: Vec<ObjectIndex> = Vec::new();
         for ref_obj_id in &expression_references.object_ids_to_reference {
-            if ref_obj_id.eq(&obj.info.id) {
-                let mut locked = obj.resolved.write().unwrap(); // TODO - handle error
-                match &mut *locked {
-                    TimelineObjectResolvingStatus::Pending => {
-                        // This is fine, we will resolve it shortly
-                    }
-                    TimelineObjectResolvingStatus::InProgress(progress) => {
-                        progress.is_self_referencing = true;
-                    }
-                    TimelineObjectResolvingStatus::Complete(_) => {
-                        // This is fine. Very good actually
-                    }
-                };
-            } else {
-                if let Some(ref_obj) = ctx.get_object(ref_obj_id) {
+            if let Some(ref_obj) = ctx.get_object_index(ref_obj_id) {
+                if ref_obj == obj {
+                    ctx.mark_self_referencing(obj);
+                } else {
                     referenced_objs.push(ref_obj);
                 }
             }
         }
 
-        if obj.is_self_referencing() {
+        if ctx.is_self_referencing(obj) {
             // Exclude any self-referencing objects:
-            referenced_objs = referenced_objs
-                .into_iter()
-                .filter(|ref_obj| !ref_obj.is_self_referencing())
-                .collect::<Vec<_>>();
+            referenced_objs.retain(|ref_obj| !ctx.is_self_referencing(*ref_obj));
         }
 
         if !referenced_objs.is_empty() {
@@ -193,54 +179,56 @@ fn lookup_expression_str(
                 }
             };
 
-            if ref_type == ObjectRefType::Duration {
-                let mut instance_durations = Vec::new();
-                for ref_obj in referenced_objs {
-                    ctx.resolve_object(ref_obj)?;
+            for ref_obj in &referenced_objs {
+                ctx.resolve_object(*ref_obj)?;
+            }
 
-                    let obj_is_self_referencing = obj.is_self_referencing();
-                    let locked_ref = ref_obj.resolved.read().unwrap(); // TODO - handle error
-                    match &*locked_ref {
+            // Everything is resolved, so the results can now be borrowed from the arena
+            let ctx: &ResolverContext = ctx;
+            let obj_is_self_referencing = ctx.is_self_referencing(obj);
+            let referenced_results = referenced_objs
+                .iter()
+                .filter_map(|ref_obj| {
+                    let ref_obj = ctx.get_object(*ref_obj);
+                    match &ref_obj.resolved {
                         TimelineObjectResolvingStatus::Pending => {
                             // Nothing to do
+                            None
                         }
                         TimelineObjectResolvingStatus::InProgress(_) => {
                             // Nothing to do
+                            None
                         }
                         TimelineObjectResolvingStatus::Complete(res) => {
                             if obj_is_self_referencing && res.is_self_referencing {
                                 // If the querying object is self-referencing, exclude any other self-referencing objects,
                                 // ignore the object
+                                None
                             } else {
-                                if let Some(first_instance) = res.instances.first() {
-                                    if let Some(end) = first_instance.end {
-                                        let duration = end - first_instance.start;
-                                        let mut references = HashSet::new();
-                                        references
-                                            .extend(first_instance.references.iter().cloned());
-                                        references.insert(ref_obj.info.id.clone());
-
-                                        instance_durations.push(TimeWithReference {
-                                            value: duration,
-                                            references,
-                                        })
-                                    }
-                                }
+                                Some((ref_obj, res))
                             }
                         }
                     }
-                }
+                })
+                .collect::<Vec<_>>();
 
+            if ref_type == ObjectRefType::Duration {
                 let mut result: Option<TimeWithReference> = None;
-                for d in instance_durations {
-                    match &result {
-                        Some(first2) => {
-                            if d.value < first2.value {
-                                result = Some(d);
+                for (ref_obj, res) in referenced_results {
+                    if let Some(first_instance) = res.instances.first() {
+                        if let Some(end) = first_instance.end {
+                            let duration = end - first_instance.start;
+                            if result.as_ref().map(|r| duration < r.value).unwrap_or(true) {
+                                result = Some(TimeWithReference {
+                                    value: duration,
+                                    references: ReferencesBuilder::new()
+                                        .add(&first_instance.references)
+                                        .add_id(&ref_obj.info.id)
+                                        .done(),
+                                });
                             }
                         }
-                        None => result = Some(d),
-                    };
+                    }
                 }
 
                 Ok(LookupExpressionResult {
@@ -250,45 +238,26 @@ fn lookup_expression_str(
                     all_references: expression_references.all_references,
                 })
             } else {
-                let mut return_instances: Vec<TimelineObjectInstance> = Vec::new();
-
                 let invert_and_ignore_first_if_zero = ref_type == ObjectRefType::End;
 
-                for ref_obj in referenced_objs {
-                    ctx.resolve_object(ref_obj)?;
+                let referenced_instances = referenced_results
+                    .iter()
+                    .flat_map(|(_, res)| res.instances.iter())
+                    .collect::<Vec<_>>();
 
-                    let obj_is_self_referencing = obj.is_self_referencing();
-                    let locked_ref = ref_obj.resolved.read().unwrap(); // TODO - handle error
-                    match &*locked_ref {
-                        TimelineObjectResolvingStatus::Pending => {
-                            // Nothing to do
-                        }
-                        TimelineObjectResolvingStatus::InProgress(_) => {
-                            // Nothing to do
-                        }
-                        TimelineObjectResolvingStatus::Complete(res) => {
-                            if obj_is_self_referencing && res.is_self_referencing {
-                                // If the querying object is self-referencing, exclude any other self-referencing objects,
-                                // ignore the object
-                            } else {
-                                return_instances.extend(res.instances.iter().cloned());
-                            }
-                        }
-                    }
-                }
-
-                if !return_instances.is_empty() {
-                    if invert_and_ignore_first_if_zero {
-                        return_instances = invert_instances(ctx, &return_instances);
+                if !referenced_instances.is_empty() {
+                    let return_instances = if invert_and_ignore_first_if_zero {
+                        let mut return_instances = invert_instances(ctx, &referenced_instances);
 
                         if let Some(first) = return_instances.first() {
                             if first.start == 0 {
                                 return_instances.remove(0);
                             }
                         }
+                        return_instances
                     } else {
-                        return_instances = clean_instances(ctx, &return_instances, true, true);
-                    }
+                        clean_instances(ctx, &referenced_instances, true, true)
+                    };
 
                     Ok(LookupExpressionResult {
                         result: LookupExpressionResultType::Instances(return_instances),
@@ -313,8 +282,8 @@ fn lookup_expression_str(
 }
 
 fn lookup_expression_obj(
-    ctx: &ResolverContext,
-    obj: &ResolvingTimelineObject,
+    ctx: &mut ResolverContext,
+    obj: ObjectIndex,
     expr: &ExpressionObj,
     default_ref_type: &ObjectRefType,
 ) -> Result<LookupExpressionResult, ResolveError> {
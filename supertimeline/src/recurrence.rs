@@ -0,0 +1,669 @@
+#[cfg(not(feature = "recurrence"))]
+use crate::{
+    api::ResolveOptions, instance::TimelineObjectInstance, resolver::ResolverContext,
+    util::RepeatLimits,
+};
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+/** A calendar based recurrence of an object, such as "every weekday at 06:00" */
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct TimelineRecurrence {
+    /** The RRULE-style rule, eg "FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR;BYHOUR=6;BYMINUTE=0".
+     * Supported are FREQ (DAILY, WEEKLY, MONTHLY or YEARLY), INTERVAL, COUNT, UNTIL, BYMONTH, BYMONTHDAY, BYDAY, BYHOUR, BYMINUTE and BYSECOND.
+     * Ordinals in BYDAY (eg 1MO, -1FR) are relative to the month, and only allowed with MONTHLY or YEARLY. BYMONTHDAY is not allowed with WEEKLY.
+     * The start of the enable is used as DTSTART, and the duration as the length of each occurrence
+     */
+    pub rule: String,
+    /** (Optional) The IANA timezone to evaluate the rule in, eg "Europe/London". Defaults to UTC */
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(skip_serializing_if = "Option::is_none")
+    )]
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum RecurrenceError {
+    /** The rule could not be parsed. Contains the offending part */
+    InvalidRule(String),
+    /** The timezone is not known */
+    UnknownTimezone(String),
+    /** The library was built without the recurrence feature */
+    NotSupported,
+}
+
+#[cfg(not(feature = "recurrence"))]
+pub fn apply_recurrence_instances(
+    _ctx: &ResolverContext,
+    _instances: Vec<TimelineObjectInstance>,
+    _recurrence: &TimelineRecurrence,
+    _repeat_limits: &RepeatLimits,
+    _options: &ResolveOptions,
+) -> Result<Vec<TimelineObjectInstance>, RecurrenceError> {
+    Err(RecurrenceError::NotSupported)
+}
+
+#[cfg(feature = "recurrence")]
+pub use self::rrule::apply_recurrence_instances;
+
+#[cfg(feature = "recurrence")]
+mod rrule {
+    use super::{RecurrenceError, TimelineRecurrence};
+    use crate::api::{ResolveOptions, DEFAULT_LIMIT_COUNT};
+    use crate::instance::TimelineObjectInstance;
+    use crate::resolver::ResolverContext;
+    use crate::util::{
+        clean_instances, create_repeated_instance, find_repeat_cap, RepeatLimits, Time,
+    };
+    use chrono::{
+        DateTime, Datelike, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone,
+        Timelike, Weekday,
+    };
+    use chrono_tz::Tz;
+    use std::collections::{HashSet, VecDeque};
+    use std::convert::TryFrom;
+
+    /** Give up looking for an occurrence after this many periods in a row without one */
+    const MAX_EMPTY_PERIODS: usize = 1000;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Frequency {
+        Daily,
+        Weekly,
+        Monthly,
+        Yearly,
+    }
+
+    #[derive(Debug)]
+    enum Until {
+        Utc(Time),
+        Local(NaiveDateTime),
+    }
+
+    #[derive(Debug)]
+    struct RecurrenceRule {
+        frequency: Frequency,
+        interval: i64,
+        count: Option<usize>,
+        until: Option<Until>,
+        by_month: Vec<u32>,
+        by_month_day: Vec<i32>,
+        by_day: Vec<(Option<i32>, Weekday)>,
+        by_hour: Vec<u32>,
+        by_minute: Vec<u32>,
+        by_second: Vec<u32>,
+    }
+
+    fn parse_list<T, F>(value: &str, part: &str, parse: F) -> Result<Vec<T>, RecurrenceError>
+    where
+        F: Fn(&str) -> Option<T>,
+    {
+        value
+            .split(',')
+            .map(|v| parse(v.trim()).ok_or_else(|| RecurrenceError::InvalidRule(part.to_string())))
+            .collect()
+    }
+
+    fn parse_ranged(value: &str, min: u32, max: u32) -> Option<u32> {
+        value.parse::<u32>().ok().filter(|v| *v >= min && *v <= max)
+    }
+
+    fn parse_weekday(value: &str) -> Option<Weekday> {
+        match value {
+            "MO" => Some(Weekday::Mon),
+            "TU" => Some(Weekday::Tue),
+            "WE" => Some(Weekday::Wed),
+            "TH" => Some(Weekday::Thu),
+            "FR" => Some(Weekday::Fri),
+            "SA" => Some(Weekday::Sat),
+            "SU" => Some(Weekday::Sun),
+            _ => None,
+        }
+    }
+
+    fn parse_by_day(value: &str) -> Option<(Option<i32>, Weekday)> {
+        if value.len() < 2 {
+            return None;
+        }
+        let (ordinal, day) = value.split_at(value.len() - 2);
+        let weekday = parse_weekday(day)?;
+        if ordinal.is_empty() {
+            Some((None, weekday))
+        } else {
+            let ordinal = ordinal.trim_start_matches('+').parse::<i32>().ok()?;
+            if ordinal == 0 || ordinal.abs() > 5 {
+                None
+            } else {
+                Some((Some(ordinal), weekday))
+            }
+        }
+    }
+
+    fn parse_until(value: &str) -> Option<Until> {
+        let (value, is_utc) = if let Some(stripped) = value.strip_suffix('Z') {
+            (stripped, true)
+        } else {
+            (value, false)
+        };
+
+        let local = if value.len() == 8 {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .ok()?
+                .and_time(NaiveTime::MIN)
+        } else {
+            NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?
+        };
+
+        if is_utc {
+            let ms = local.and_utc().timestamp_millis();
+            if ms < 0 {
+                None
+            } else {
+                Some(Until::Utc(ms as Time))
+            }
+        } else {
+            Some(Until::Local(local))
+        }
+    }
+
+    impl RecurrenceRule {
+        fn parse(rule: &str) -> Result<RecurrenceRule, RecurrenceError> {
+            let mut frequency = None;
+            let mut result = RecurrenceRule {
+                frequency: Frequency::Daily,
+                interval: 1,
+                count: None,
+                until: None,
+                by_month: Vec::new(),
+                by_month_day: Vec::new(),
+                by_day: Vec::new(),
+                by_hour: Vec::new(),
+                by_minute: Vec::new(),
+                by_second: Vec::new(),
+            };
+
+            let rule = rule.trim();
+            let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+            for part in rule.split(';').filter(|p| !p.trim().is_empty()) {
+                let invalid = || RecurrenceError::InvalidRule(part.to_string());
+                let mut split = part.splitn(2, '=');
+                let key = split.next().unwrap_or("").trim().to_uppercase();
+                let value = split.next().ok_or_else(invalid)?.trim().to_uppercase();
+
+                match key.as_str() {
+                    "FREQ" => {
+                        frequency = Some(match value.as_str() {
+                            "DAILY" => Frequency::Daily,
+                            "WEEKLY" => Frequency::Weekly,
+                            "MONTHLY" => Frequency::Monthly,
+                            "YEARLY" => Frequency::Yearly,
+                            _ => return Err(invalid()),
+                        })
+                    }
+                    "INTERVAL" => {
+                        result.interval = value
+                            .parse::<i64>()
+                            .ok()
+                            .filter(|v| *v > 0)
+                            .ok_or_else(invalid)?
+                    }
+                    "COUNT" => result.count = Some(value.parse::<usize>().map_err(|_| invalid())?),
+                    "UNTIL" => result.until = Some(parse_until(&value).ok_or_else(invalid)?),
+                    "BYMONTH" => {
+                        result.by_month = parse_list(&value, part, |v| parse_ranged(v, 1, 12))?
+                    }
+                    "BYMONTHDAY" => {
+                        result.by_month_day = parse_list(&value, part, |v| {
+                            v.parse::<i32>().ok().filter(|d| *d != 0 && d.abs() <= 31)
+                        })?
+                    }
+                    "BYDAY" => result.by_day = parse_list(&value, part, parse_by_day)?,
+                    "BYHOUR" => {
+                        result.by_hour = parse_list(&value, part, |v| parse_ranged(v, 0, 23))?
+                    }
+                    "BYMINUTE" => {
+                        result.by_minute = parse_list(&value, part, |v| parse_ranged(v, 0, 59))?
+                    }
+                    "BYSECOND" => {
+                        result.by_second = parse_list(&value, part, |v| parse_ranged(v, 0, 59))?
+                    }
+                    "WKST" => {
+                        // Weeks always start on a monday
+                        if value != "MO" {
+                            return Err(invalid());
+                        }
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+
+            let frequency =
+                frequency.ok_or_else(|| RecurrenceError::InvalidRule(rule.to_string()))?;
+            let by_day_ordinals = result.by_day.iter().any(|(ordinal, _)| ordinal.is_some());
+            match frequency {
+                // The nth weekday only has a meaning within a month or a year
+                Frequency::Daily | Frequency::Weekly if by_day_ordinals => {
+                    Err(RecurrenceError::InvalidRule(rule.to_string()))
+                }
+                Frequency::Weekly if !result.by_month_day.is_empty() => {
+                    Err(RecurrenceError::InvalidRule(rule.to_string()))
+                }
+                _ => {
+                    result.frequency = frequency;
+                    Ok(result)
+                }
+            }
+        }
+
+        fn matches_month(&self, date: &NaiveDate) -> bool {
+            self.by_month.is_empty() || self.by_month.contains(&date.month())
+        }
+
+        fn matches_month_day(&self, date: &NaiveDate) -> bool {
+            self.by_month_day.is_empty() || {
+                let days_in_month = days_in_month(date.year(), date.month()) as i32;
+                let day = date.day() as i32;
+                self.by_month_day
+                    .iter()
+                    .any(|d| *d == day || days_in_month + *d + 1 == day)
+            }
+        }
+
+        fn matches_day(&self, date: &NaiveDate) -> bool {
+            self.by_day.is_empty() || {
+                let days_in_month = days_in_month(date.year(), date.month());
+                let nth = ((date.day() - 1) / 7 + 1) as i32;
+                let nth_last = -(((days_in_month - date.day()) / 7 + 1) as i32);
+                self.by_day.iter().any(|(ordinal, weekday)| {
+                    *weekday == date.weekday()
+                        && ordinal.map(|o| o == nth || o == nth_last).unwrap_or(true)
+                })
+            }
+        }
+
+        /** The dates in a month that the rule selects */
+        fn month_dates(&self, year: i32, month: u32, start: &NaiveDate) -> Vec<NaiveDate> {
+            (1..=days_in_month(year, month))
+                .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                .filter(|date| {
+                    if self.by_month_day.is_empty() && self.by_day.is_empty() {
+                        // Default to the day of month of the start
+                        date.day() == start.day()
+                    } else {
+                        self.matches_month_day(date) && self.matches_day(date)
+                    }
+                })
+                .collect()
+        }
+
+        /** The local dates in the period with the given index, or None when the period is past the supported dates */
+        fn period_dates(
+            &self,
+            anchor: &NaiveDate,
+            start: &NaiveDate,
+            period: i64,
+        ) -> Option<Vec<NaiveDate>> {
+            let steps = period.checked_mul(self.interval)?;
+            let dates = match self.frequency {
+                Frequency::Daily => {
+                    let date = anchor.checked_add_signed(TimeDelta::try_days(steps)?)?;
+                    if self.matches_month(&date)
+                        && self.matches_month_day(&date)
+                        && self.by_day.iter().all(|(o, _)| o.is_none())
+                        && self.matches_day(&date)
+                    {
+                        vec![date]
+                    } else {
+                        Vec::new()
+                    }
+                }
+                Frequency::Weekly => {
+                    let week =
+                        anchor.checked_add_signed(TimeDelta::try_days(steps.checked_mul(7)?)?)?;
+                    let mut dates = Vec::new();
+                    for d in 0..7 {
+                        let date = week.checked_add_signed(TimeDelta::try_days(d)?)?;
+                        let weekday_matches = if self.by_day.is_empty() {
+                            date.weekday() == start.weekday()
+                        } else {
+                            self.by_day.iter().any(|(_, w)| *w == date.weekday())
+                        };
+                        if weekday_matches && self.matches_month(&date) {
+                            dates.push(date);
+                        }
+                    }
+                    dates
+                }
+                Frequency::Monthly => {
+                    let (year, month) = add_months(anchor.year(), anchor.month(), steps)?;
+                    NaiveDate::from_ymd_opt(year, month, 1)?;
+                    if self.by_month.is_empty() || self.by_month.contains(&month) {
+                        self.month_dates(year, month, start)
+                    } else {
+                        Vec::new()
+                    }
+                }
+                Frequency::Yearly => {
+                    let year = i32::try_from(steps)
+                        .ok()
+                        .and_then(|steps| anchor.year().checked_add(steps))?;
+                    NaiveDate::from_ymd_opt(year, 1, 1)?;
+                    let months = if self.by_month.is_empty() {
+                        vec![start.month()]
+                    } else {
+                        self.by_month.clone()
+                    };
+                    let mut dates = months
+                        .into_iter()
+                        .flat_map(|month| self.month_dates(year, month, start))
+                        .collect::<Vec<_>>();
+                    dates.sort();
+                    dates
+                }
+            };
+            Some(dates)
+        }
+
+        /** The index of the period that contains the date */
+        fn period_of(&self, anchor: &NaiveDate, date: &NaiveDate) -> i64 {
+            let units = match self.frequency {
+                Frequency::Daily => (*date - *anchor).num_days(),
+                Frequency::Weekly => (*date - *anchor).num_days().div_euclid(7),
+                Frequency::Monthly => {
+                    (date.year() - anchor.year()) as i64 * 12 + date.month() as i64
+                        - anchor.month() as i64
+                }
+                Frequency::Yearly => (date.year() - anchor.year()) as i64,
+            };
+            units.div_euclid(self.interval)
+        }
+
+        fn times(&self, start: &NaiveDateTime) -> Vec<NaiveTime> {
+            let or_default = |values: &Vec<u32>, default: u32| {
+                if values.is_empty() {
+                    vec![default]
+                } else {
+                    values.clone()
+                }
+            };
+
+            let mut times = Vec::new();
+            for hour in or_default(&self.by_hour, start.hour()) {
+                for minute in or_default(&self.by_minute, start.minute()) {
+                    for second in or_default(&self.by_second, start.second()) {
+                        if let Some(time) = NaiveTime::from_hms_opt(hour, minute, second) {
+                            times.push(time);
+                        }
+                    }
+                }
+            }
+            times.sort();
+            times
+        }
+    }
+
+    fn days_in_month(year: i32, month: u32) -> u32 {
+        add_months(year, month, 1)
+            .and_then(|(next_year, next_month)| NaiveDate::from_ymd_opt(next_year, next_month, 1))
+            .and_then(|d| d.pred_opt())
+            .map(|d| d.day())
+            .unwrap_or(28)
+    }
+
+    fn add_months(year: i32, month: u32, months: i64) -> Option<(i32, u32)> {
+        let total = (year as i64 * 12 + (month as i64 - 1)).checked_add(months)?;
+        Some((
+            i32::try_from(total.div_euclid(12)).ok()?,
+            (total.rem_euclid(12) + 1) as u32,
+        ))
+    }
+
+    /** Convert a local time to a point in time. Times skipped by DST are moved forward, repeated times use the first */
+    fn to_time(tz: &Tz, local: &NaiveDateTime) -> Option<Time> {
+        let resolved = match tz.from_local_datetime(local) {
+            LocalResult::Single(dt) => Some(dt),
+            LocalResult::Ambiguous(earliest, _) => Some(earliest),
+            LocalResult::None => local
+                .checked_add_signed(TimeDelta::hours(1))
+                .and_then(|local| tz.from_local_datetime(&local).earliest()),
+        };
+        resolved
+            .map(|dt| dt.timestamp_millis())
+            .filter(|ms| *ms >= 0)
+            .map(|ms| ms as Time)
+    }
+
+    fn to_local(tz: &Tz, time: Time) -> DateTime<Tz> {
+        tz.timestamp_millis_opt(time as i64)
+            .single()
+            .unwrap_or_else(|| tz.timestamp_millis_opt(0).unwrap())
+    }
+
+    /** Iterator over the occurrences of a rule, in chronological order */
+    #[derive(Clone)]
+    struct Occurrences<'a> {
+        rule: &'a RecurrenceRule,
+        tz: &'a Tz,
+        anchor: NaiveDate,
+        start_local: NaiveDateTime,
+        start: Time,
+        until: Option<Time>,
+        count: Option<usize>,
+        times: Vec<NaiveTime>,
+
+        period: i64,
+        emitted: usize,
+        buffer: VecDeque<Time>,
+        done: bool,
+    }
+    impl<'a> Occurrences<'a> {
+        fn new(
+            rule: &'a RecurrenceRule,
+            tz: &'a Tz,
+            start: Time,
+            count: Option<usize>,
+            first_period: i64,
+        ) -> Occurrences<'a> {
+            let start_local = to_local(tz, start).naive_local();
+            let start_date = start_local.date();
+            let anchor = match rule.frequency {
+                Frequency::Weekly => start_date
+                    .checked_sub_signed(TimeDelta::days(
+                        start_date.weekday().num_days_from_monday() as i64,
+                    ))
+                    .unwrap_or(start_date),
+                Frequency::Monthly => {
+                    NaiveDate::from_ymd_opt(start_date.year(), start_date.month(), 1)
+                        .unwrap_or(start_date)
+                }
+                Frequency::Yearly => {
+                    NaiveDate::from_ymd_opt(start_date.year(), 1, 1).unwrap_or(start_date)
+                }
+                Frequency::Daily => start_date,
+            };
+            let until = rule.until.as_ref().and_then(|until| match until {
+                Until::Utc(time) => Some(*time),
+                Until::Local(local) => to_time(tz, local),
+            });
+
+            Occurrences {
+                rule,
+                tz,
+                anchor,
+                times: rule.times(&start_local),
+                start_local,
+                start,
+                until,
+                count,
+                period: first_period.max(0),
+                emitted: 0,
+                buffer: VecDeque::new(),
+                done: false,
+            }
+        }
+
+        fn fill_buffer(&mut self) {
+            let mut empty_periods = 0;
+            while self.buffer.is_empty() && !self.done {
+                let dates = match self.rule.period_dates(
+                    &self.anchor,
+                    &self.start_local.date(),
+                    self.period,
+                ) {
+                    Some(dates) => dates,
+                    None => {
+                        // The rule has stepped past the dates that can be represented
+                        self.done = true;
+                        break;
+                    }
+                };
+                self.period += 1;
+
+                let mut times = dates
+                    .iter()
+                    .flat_map(|date| self.times.iter().map(move |time| date.and_time(*time)))
+                    .filter_map(|local| to_time(self.tz, &local))
+                    .filter(|time| *time >= self.start)
+                    .collect::<Vec<_>>();
+                times.sort_unstable();
+                times.dedup();
+
+                if times.is_empty() {
+                    empty_periods += 1;
+                    if empty_periods >= MAX_EMPTY_PERIODS {
+                        self.done = true;
+                    }
+                } else {
+                    self.buffer.extend(times);
+                }
+            }
+        }
+    }
+    impl<'a> Iterator for Occurrences<'a> {
+        type Item = Time;
+
+        fn next(&mut self) -> Option<Time> {
+            if self.count.map(|c| self.emitted >= c).unwrap_or(false) {
+                return None;
+            }
+
+            self.fill_buffer();
+            let next = self.buffer.pop_front()?;
+            if self.until.map(|until| next > until).unwrap_or(false) {
+                self.done = true;
+                self.buffer.clear();
+                return None;
+            }
+
+            self.emitted += 1;
+            Some(next)
+        }
+    }
+
+    /** Find the occurrences from the last one at or before time */
+    fn occurrences_from<'a>(
+        rule: &'a RecurrenceRule,
+        tz: &'a Tz,
+        start: Time,
+        count: Option<usize>,
+        time: Time,
+    ) -> impl Iterator<Item = Time> + 'a {
+        let mut occurrences = if count.is_some() || time <= start {
+            // Counting has to begin at the start
+            Occurrences::new(rule, tz, start, count, 0)
+        } else {
+            // Jump close to the time, going back further until an occurrence before it is found
+            let anchor = Occurrences::new(rule, tz, start, count, 0).anchor;
+            let time_period = rule.period_of(&anchor, &to_local(tz, time).date_naive());
+            let mut back = 1;
+            loop {
+                let first_period = time_period - back;
+                let candidate = Occurrences::new(rule, tz, start, count, first_period);
+                let found = candidate.clone().next().map(|t| t <= time).unwrap_or(false);
+                if found || first_period <= 0 {
+                    break candidate;
+                }
+                back *= 2;
+            }
+        };
+
+        // Skip ahead to the last occurrence at or before the time
+        let mut previous = None;
+        loop {
+            match occurrences.next() {
+                Some(next) if next <= time => previous = Some(next),
+                next => return previous.into_iter().chain(next).chain(occurrences),
+            }
+        }
+    }
+
+    pub fn apply_recurrence_instances(
+        ctx: &ResolverContext,
+        instances: Vec<TimelineObjectInstance>,
+        recurrence: &TimelineRecurrence,
+        repeat_limits: &RepeatLimits,
+        options: &ResolveOptions,
+    ) -> Result<Vec<TimelineObjectInstance>, RecurrenceError> {
+        let rule = RecurrenceRule::parse(&recurrence.rule)?;
+        let tz = if let Some(timezone) = &recurrence.timezone {
+            timezone
+                .parse::<Tz>()
+                .map_err(|_| RecurrenceError::UnknownTimezone(timezone.clone()))?
+        } else {
+            chrono_tz::UTC
+        };
+
+        let count = match (rule.count, repeat_limits.count) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let mut repeated_instances = Vec::new();
+        for instance in &instances {
+            let duration = instance.end.map(|end| end - instance.start);
+            let cap = find_repeat_cap(instance);
+            let until = repeat_limits.until_for(instance);
+
+            let occurrences = occurrences_from(&rule, &tz, instance.start, count, options.time);
+
+            let limit = options.limit_count.unwrap_or(DEFAULT_LIMIT_COUNT);
+            for (i, start_time) in occurrences.enumerate() {
+                if let Some(until) = until {
+                    if start_time >= until.value {
+                        break;
+                    }
+                }
+                if cap
+                    .and_then(|cap| cap.end)
+                    .is_some_and(|end| start_time >= end)
+                {
+                    // The parent has ended
+                    break;
+                }
+                if i >= limit || options.limit_time.is_some_and(|t| start_time >= t) {
+                    // There are more occurrences than were resolved
+                    ctx.truncated_at(start_time);
+                    break;
+                }
+
+                let end_time = duration.map(|duration| start_time + duration);
+                if let Some(repeated_instance) = create_repeated_instance(
+                    ctx,
+                    instance,
+                    cap,
+                    until,
+                    &HashSet::new(),
+                    start_time,
+                    end_time,
+                ) {
+                    repeated_instances.push(repeated_instance);
+                }
+            }
+        }
+
+        Ok(clean_instances(ctx, &repeated_instances, false, false))
+    }
+}
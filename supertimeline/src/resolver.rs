@@ -6,21 +6,29 @@ use crate::expression::interpret_expression;
 use crate::expression::is_constant;
 use crate::expression::ExpressionError;
 use crate::expression::{hack_boolean_expression, simplify_expression, Expression};
+use crate::instance::GroupMode;
+use crate::instance::ResolvedPrioritySegment;
 use crate::instance::TimelineObjectInfo;
 use crate::instance::TimelineObjectInstance;
 use crate::instance::TimelineObjectResolved;
 use crate::instance::TimelineObjectResolvedWip;
+use crate::instance::TimelinePause;
 use crate::lookup_expression::{lookup_expression, LookupExpressionResultType};
+use crate::recurrence::{apply_recurrence_instances, RecurrenceError};
 use crate::references::ReferencesBuilder;
+use crate::state::{compare_aspiring_instances, ResolvedTimelineObjectInstance};
 use crate::util::apply_parent_instances;
 use crate::util::apply_repeating_instances;
 use crate::util::cap_instance;
+use crate::util::cap_instance_start;
+use crate::util::RepeatLimits;
 use crate::util::Time;
+use crate::util::{set_instance_end_time, set_instance_start_time};
 use core::cmp::min;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::RwLock;
+use std::sync::Arc;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ObjectRefType {
@@ -43,18 +51,13 @@ pub enum ResolveError {
     ResolvedWhilePending(String),
     ResolvedWhileResolvec(String),
     UnresolvedObjects(Vec<String>),
+    BadRecurrence((String, RecurrenceError)),
+    BadPlaybackRate(String),
 }
 
 pub struct ResolvingTimelineObject {
-    // pub object: Box<dyn IsTimelineObject>,
-    pub resolved: RwLock<TimelineObjectResolvingStatus>,
-    pub info: TimelineObjectInfo,
-}
-impl ResolvingTimelineObject {
-    pub fn is_self_referencing(&self) -> bool {
-        let locked = self.resolved.read().unwrap(); // TODO - handle error
-        locked.is_self_referencing()
-    }
+    pub resolved: TimelineObjectResolvingStatus,
+    pub info: Arc<TimelineObjectInfo>,
 }
 
 #[derive(Debug, Clone)]
@@ -76,40 +79,149 @@ impl TimelineObjectResolvingStatus {
     }
 }
 
+/** Index of an object inside of ResolvingObjects */
+pub type ObjectIndex = usize;
+
+/** Arena of the objects being resolved, addressable by index or by id */
+#[derive(Default)]
+pub struct ResolvingObjects {
+    objects: Vec<ResolvingTimelineObject>,
+    indices: HashMap<String, ObjectIndex>,
+}
+impl ResolvingObjects {
+    pub fn new() -> ResolvingObjects {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, obj: ResolvingTimelineObject) {
+        if let Some(index) = self.indices.get(&obj.info.id) {
+            // TODO - duplicate id check. For now the last one wins
+            self.objects[*index] = obj;
+        } else {
+            self.indices.insert(obj.info.id.clone(), self.objects.len());
+            self.objects.push(obj);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn index_of(&self, id: &str) -> Option<ObjectIndex> {
+        self.indices.get(id).copied()
+    }
+
+    pub fn get(&self, index: ObjectIndex) -> &ResolvingTimelineObject {
+        &self.objects[index]
+    }
+    pub fn get_mut(&mut self, index: ObjectIndex) -> &mut ResolvingTimelineObject {
+        &mut self.objects[index]
+    }
+}
+impl IntoIterator for ResolvingObjects {
+    type Item = ResolvingTimelineObject;
+    type IntoIter = std::vec::IntoIter<ResolvingTimelineObject>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.objects.into_iter()
+    }
+}
+
 // TODO - this should be split into a result and a context
 pub struct ResolverContext<'a> {
     pub options: &'a ResolveOptions,
-    /** Map of all objects on timeline */
-    pub objects: HashMap<String, ResolvingTimelineObject>,
+    /** Arena of all objects on timeline */
+    pub objects: ResolvingObjects,
     /** Map of all classes on timeline, maps className to object ids */
     classes: &'a HashMap<String, Vec<String>>,
     /** Map of the object ids, per layer */
     layers: &'a HashMap<String, Vec<String>>,
     // pub statistics: ResolveStatistics,
-    next_id: AtomicUsize,
+    next_id: Cell<usize>,
+    /** The earliest time where a repeating object had more repeats than were resolved */
+    valid_until: Cell<Option<Time>>,
+    /** The indices of the children and keyframes, per parent id */
+    children: HashMap<String, Vec<ObjectIndex>>,
+    /** The exclusive groups that have started to cap their children */
+    exclusive_groups: HashSet<ObjectIndex>,
 }
 
 impl<'a> ResolverContext<'a> {
     pub fn create(
         resolved_timeline: &'a ResolvedTimeline,
-        objects: HashMap<String, ResolvingTimelineObject>,
+        objects: ResolvingObjects,
     ) -> ResolverContext<'a> {
+        let mut children: HashMap<String, Vec<ObjectIndex>> = HashMap::new();
+        for index in 0..objects.len() {
+            if let Some(parent_id) = &objects.get(index).info.parent_id {
+                children.entry(parent_id.clone()).or_default().push(index);
+            }
+        }
+
         ResolverContext {
             options: &resolved_timeline.options,
             objects,
             classes: &resolved_timeline.classes,
             layers: &resolved_timeline.layers,
-            next_id: AtomicUsize::new(0),
+            next_id: Cell::new(0),
+            valid_until: Cell::new(None),
+            children,
+            exclusive_groups: HashSet::new(),
+        }
+    }
+
+    /** The options to use when resolving the object, with its own limits applied */
+    fn options_for(&self, info: &TimelineObjectInfo) -> ResolveOptions {
+        ResolveOptions {
+            time: self.options.time,
+            limit_count: info.limit_count.or(self.options.limit_count),
+            limit_time: info.limit_time.or(self.options.limit_time),
         }
     }
 
+    /** Marks the resolved result as incomplete from the given time */
+    pub fn truncated_at(&self, time: Time) {
+        let valid_until = self.valid_until.get().map_or(time, |t| min(t, time));
+        self.valid_until.set(Some(valid_until));
+    }
+
+    pub fn valid_until(&self) -> Option<Time> {
+        self.valid_until.get()
+    }
+
+    /** Continue the generated ids from an earlier resolve, so they don't clash with its instances */
+    pub fn continue_ids_from(&self, next_id: usize) {
+        self.next_id.set(next_id);
+    }
+
     pub fn generate_id(&self) -> String {
-        let index = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let index = self.next_id.get();
+        self.next_id.set(index + 1);
         format!("@{}", index)
     }
 
-    pub fn get_object(&self, id: &str) -> Option<&ResolvingTimelineObject> {
-        self.objects.get(id)
+    pub fn get_object_index(&self, id: &str) -> Option<ObjectIndex> {
+        self.objects.index_of(id)
+    }
+    pub fn get_object(&self, index: ObjectIndex) -> &ResolvingTimelineObject {
+        self.objects.get(index)
+    }
+
+    pub fn is_self_referencing(&self, index: ObjectIndex) -> bool {
+        self.objects.get(index).resolved.is_self_referencing()
+    }
+    pub fn mark_self_referencing(&mut self, index: ObjectIndex) {
+        match &mut self.objects.get_mut(index).resolved {
+            TimelineObjectResolvingStatus::Pending => {
+                // This is fine, we will resolve it shortly
+            }
+            TimelineObjectResolvingStatus::InProgress(progress) => {
+                progress.is_self_referencing = true;
+            }
+            TimelineObjectResolvingStatus::Complete(_) => {
+                // This is fine. Very good actually
+            }
+        }
     }
 
     pub fn get_object_ids_for_class(&self, class: &str) -> Option<&Vec<String>> {
@@ -119,38 +231,44 @@ impl<'a> ResolverContext<'a> {
         self.layers.get(layer)
     }
 
-    pub fn resolve_object(&self, obj: &ResolvingTimelineObject) -> Result<(), ResolveError> {
-        {
-            let mut current_status = obj.resolved.write().unwrap(); // TODO - handle error
-            match &mut *current_status {
-                TimelineObjectResolvingStatus::Complete(_) => {
-                    // Already resolved
-                    return Ok(());
-                }
-                TimelineObjectResolvingStatus::InProgress(progress) => {
-                    // In progress means we hit a circular route
-                    // TODO - this will need to track callers/something when threading
-                    progress.is_self_referencing = true;
+    pub fn resolve_object(&mut self, index: ObjectIndex) -> Result<(), ResolveError> {
+        let obj = self.objects.get_mut(index);
+        match &mut obj.resolved {
+            TimelineObjectResolvingStatus::Complete(_) => {
+                // Already resolved
+                return Ok(());
+            }
+            TimelineObjectResolvingStatus::InProgress(progress) => {
+                // In progress means we hit a circular route
+                progress.is_self_referencing = true;
 
-                    return Err(ResolveError::CircularDependency(obj.info.id.to_string()));
-                }
-                TimelineObjectResolvingStatus::Pending => {
-                    // Mark it as in progress, and release the lock
-                    *current_status =
-                        TimelineObjectResolvingStatus::InProgress(TimelineObjectResolvedWip {
-                            is_self_referencing: false,
-                        });
-                }
-            };
+                return Err(ResolveError::CircularDependency(obj.info.id.to_string()));
+            }
+            TimelineObjectResolvingStatus::Pending => {
+                // Mark it as in progress
+                obj.resolved =
+                    TimelineObjectResolvingStatus::InProgress(TimelineObjectResolvedWip {
+                        is_self_referencing: false,
+                    });
+            }
+        };
+
+        // The info is shared, so that the arena is free to be mutated while resolving
+        let info = obj.info.clone();
+        let options = self.options_for(&info);
+
+        if !(info.playback_rate.is_finite() && info.playback_rate > 0.0) {
+            return Err(ResolveError::BadPlaybackRate(info.id.to_string()));
         }
+        let rate = info.parent_playback_rate;
 
         // Start resolving
         let mut direct_references = HashSet::new();
 
         let mut instances = Vec::new();
 
-        let obj_id = &obj.info.id;
-        for enable in &obj.info.enable {
+        let obj_id = &info.id;
+        for enable in &info.enable {
             let repeating_expr = if let Some(expr) = &enable.repeating {
                 match interpret_expression(expr) {
                     Ok(val) => val,
@@ -167,7 +285,7 @@ impl<'a> ResolverContext<'a> {
             };
 
             let looked_up_repeating =
-                lookup_expression(self, &obj, &repeating_expr, &ObjectRefType::Duration)?;
+                lookup_expression(self, index, &repeating_expr, &ObjectRefType::Duration)?;
             direct_references.extend(looked_up_repeating.all_references);
 
             let looked_up_repeating2 = match looked_up_repeating.result {
@@ -177,10 +295,54 @@ impl<'a> ResolverContext<'a> {
                         "repeating",
                     )))
                 }
+                LookupExpressionResultType::TimeRef(r) if is_constant(&repeating_expr) => {
+                    Some(scale_time_ref(r, rate))
+                }
                 LookupExpressionResultType::TimeRef(r) => Some(r),
                 LookupExpressionResultType::Null => None,
             };
 
+            let mut repeat_limits = RepeatLimits {
+                count: enable.repeat_count,
+                until: Vec::new(),
+            };
+            if let Some(repeat_until) = &enable.repeat_until {
+                let repeat_until_expr = match interpret_expression(repeat_until) {
+                    Ok(val) => val,
+                    Err(err) => {
+                        return Err(ResolveError::BadExpression((
+                            obj_id.to_string(),
+                            "repeatUntil",
+                            err,
+                        )))
+                    }
+                };
+
+                // Like .end, a plain reference means the end of the referenced object(s)
+                let lookup_repeat_until =
+                    lookup_expression(self, index, &repeat_until_expr, &ObjectRefType::End)?;
+                direct_references.extend(lookup_repeat_until.all_references);
+
+                match lookup_repeat_until.result {
+                    LookupExpressionResultType::Instances(instances) => {
+                        repeat_limits.until = instances
+                            .into_iter()
+                            .map(|instance| TimeWithReference {
+                                value: instance.start,
+                                references: ReferencesBuilder::new()
+                                    .add(&instance.references)
+                                    .add_id(&instance.id)
+                                    .done(),
+                            })
+                            .collect();
+                    }
+                    LookupExpressionResultType::TimeRef(time_ref) => {
+                        repeat_limits.until = vec![time_ref];
+                    }
+                    LookupExpressionResultType::Null => {}
+                }
+            }
+
             let hacked_while = hack_boolean_expression(enable.enable_while.as_ref());
 
             let start = simplify_expression(
@@ -195,11 +357,11 @@ impl<'a> ResolverContext<'a> {
             let mut parent_instances = None;
             let mut has_parent = false;
             let mut refer_to_parent = false;
-            if let Some(parent_id) = &obj.info.parent_id {
+            if let Some(parent_id) = &info.parent_id {
                 has_parent = true;
 
                 let expr = Expression::String(format!(r"#{}", parent_id));
-                let lookup = lookup_expression(self, &obj, &expr, &ObjectRefType::Start)?;
+                let lookup = lookup_expression(self, index, &expr, &ObjectRefType::Start)?;
                 match lookup.result {
                     LookupExpressionResultType::TimeRef(_) => {}
                     LookupExpressionResultType::Instances(instances) => {
@@ -216,11 +378,12 @@ impl<'a> ResolverContext<'a> {
                 }
             }
 
-            let lookup_start = lookup_expression(self, &obj, &start, &ObjectRefType::Start)?;
+            let lookup_start = lookup_expression(self, index, &start, &ObjectRefType::Start)?;
             direct_references.extend(lookup_start.all_references);
 
             let looked_up_starts = if refer_to_parent {
-                apply_parent_instances(self, &parent_instances, &lookup_start.result)
+                let scaled_start = scale_lookup_result(lookup_start.result, rate);
+                apply_parent_instances(self, &parent_instances, &scaled_start)
             } else {
                 lookup_start.result
             };
@@ -291,9 +454,11 @@ impl<'a> ResolverContext<'a> {
                     };
 
                     // lookedupEnds will contain an inverted list of instances. Therefore .start means an end
-                    let lookup_end = lookup_expression(self, &obj, &end_expr, &ObjectRefType::End)?;
+                    let lookup_end =
+                        lookup_expression(self, index, &end_expr, &ObjectRefType::End)?;
                     let looked_up_ends = if refer_to_parent && is_constant(&end_expr) {
-                        apply_parent_instances(self, &parent_instances, &lookup_end.result)
+                        let scaled_end = scale_lookup_result(lookup_end.result, rate);
+                        apply_parent_instances(self, &parent_instances, &scaled_end)
                     } else {
                         lookup_end.result
                     };
@@ -337,7 +502,7 @@ impl<'a> ResolverContext<'a> {
                         }
                     };
                     let lookup_duration =
-                        lookup_expression(self, &obj, &duration_expr, &ObjectRefType::Duration)?;
+                        lookup_expression(self, index, &duration_expr, &ObjectRefType::Duration)?;
 
                     direct_references.extend(lookup_duration.all_references);
 
@@ -348,15 +513,18 @@ impl<'a> ResolverContext<'a> {
                                     obj_id.to_string(),
                                     "duration",
                                 )));
-                            } else if let Some(instance) = instances.get(0) {
-                                Some(TimeWithReference {
+                            } else {
+                                instances.first().map(|instance| TimeWithReference {
                                     value: instance.start,
                                     references: instance.references.clone(),
                                 })
-                            } else {
-                                None
                             }
                         }
+                        LookupExpressionResultType::TimeRef(time_ref)
+                            if is_constant(&duration_expr) =>
+                        {
+                            Some(scale_time_ref(time_ref, rate))
+                        }
                         LookupExpressionResultType::TimeRef(time_ref) => Some(time_ref),
                         LookupExpressionResultType::Null => None,
                     };
@@ -406,27 +574,18 @@ impl<'a> ResolverContext<'a> {
                         if let Some(referred_parent_instance) = referred_parent_instance {
                             // If the child refers to its parent, there should be one specific instance to cap into
                             let capped_instance =
-                                cap_instance(instance, &[referred_parent_instance]);
-
-                            if let Some(mut capped_instance) = capped_instance {
-                                capped_instance.caps.push(Cap {
-                                    id: referred_parent_instance.id.clone(),
-                                    start: referred_parent_instance.start,
-                                    end: referred_parent_instance.end,
-                                });
+                                cap_to_parent(instance, referred_parent_instance, &info);
+
+                            if let Some(capped_instance) = capped_instance {
                                 capped_instances.push(capped_instance)
                             }
                         } else {
                             // If the child doesn't refer to its parent, it should be capped within all of its parent instances
                             for parent_instance in parent_instances {
-                                let capped_instance = cap_instance(instance, &[&parent_instance]);
-
-                                if let Some(mut capped_instance) = capped_instance {
-                                    capped_instance.caps.push(Cap {
-                                        id: parent_instance.id.clone(),
-                                        start: parent_instance.start,
-                                        end: parent_instance.end,
-                                    });
+                                let capped_instance =
+                                    cap_to_parent(instance, parent_instance, &info);
+
+                                if let Some(capped_instance) = capped_instance {
                                     capped_instances.push(capped_instance)
                                 }
                             }
@@ -437,37 +596,349 @@ impl<'a> ResolverContext<'a> {
                 new_instances = capped_instances;
             }
 
-            instances.extend(apply_repeating_instances(
-                self,
-                new_instances,
-                looked_up_repeating2,
-                &self.options,
-            ));
+            if let Some(recurrence) = &enable.recurrence {
+                let recurring_instances = apply_recurrence_instances(
+                    self,
+                    new_instances,
+                    recurrence,
+                    &repeat_limits,
+                    &options,
+                )
+                .map_err(|e| ResolveError::BadRecurrence((obj_id.to_string(), e)))?;
+                instances.extend(recurring_instances);
+            } else {
+                instances.extend(apply_repeating_instances(
+                    self,
+                    new_instances,
+                    looked_up_repeating2,
+                    &repeat_limits,
+                    &options,
+                ));
+            }
         }
 
         // filter out zero-length instances:
         instances.retain(|instance| instance.end.unwrap_or(Time::MAX) > instance.start);
 
-        let mut locked_result = obj.resolved.write().unwrap(); // TODO - handle error
-        match &*locked_result {
+        if !info.pauses.is_empty() {
+            for instance in &mut instances {
+                apply_pauses(instance, &info.pauses);
+            }
+
+            // A paused instance can't run into the next one, such as the next repeat
+            let mut starts = instances
+                .iter()
+                .map(|instance| instance.start)
+                .collect::<Vec<_>>();
+            starts.sort_unstable();
+            for instance in &mut instances {
+                let next = starts.partition_point(|start| *start <= instance.start);
+                if let Some(next_start) = starts.get(next) {
+                    if instance.end.unwrap_or(Time::MAX) > *next_start {
+                        set_instance_end_time(instance, *next_start);
+                    }
+                }
+            }
+        }
+
+        let mut priority_segments = Vec::new();
+        for segment in &info.priority_segments {
+            let hacked_while = hack_boolean_expression(Some(&segment.enable_while));
+            let segment_while =
+                simplify_expression(hacked_while.as_ref().unwrap_or(&segment.enable_while))
+                    .map_err(|e| {
+                        ResolveError::BadExpression((obj_id.to_string(), "priority", e))
+                    })?;
+
+            let lookup_while =
+                lookup_expression(self, index, &segment_while, &ObjectRefType::Start)?;
+            direct_references.extend(lookup_while.all_references);
+
+            match lookup_while.result {
+                LookupExpressionResultType::Instances(while_instances) => {
+                    priority_segments.extend(while_instances.into_iter().map(|instance| {
+                        ResolvedPrioritySegment {
+                            start: instance.start,
+                            end: instance.end,
+                            priority: segment.priority,
+                        }
+                    }))
+                }
+                LookupExpressionResultType::TimeRef(time_ref) => {
+                    priority_segments.push(ResolvedPrioritySegment {
+                        start: time_ref.value,
+                        end: None,
+                        priority: segment.priority,
+                    })
+                }
+                LookupExpressionResultType::Null => {}
+            }
+        }
+
+        let obj = self.objects.get_mut(index);
+        match &obj.resolved {
             TimelineObjectResolvingStatus::Pending => {
                 // Resolving hasn't been started, so something has messed up
-                Err(ResolveError::ResolvedWhilePending(obj.info.id.clone()))
+                return Err(ResolveError::ResolvedWhilePending(info.id.clone()));
             }
             TimelineObjectResolvingStatus::Complete(_) => {
                 // Resolving has already been completed, so something has messed up
-                Err(ResolveError::ResolvedWhileResolvec(obj.info.id.clone()))
+                return Err(ResolveError::ResolvedWhileResolvec(info.id.clone()));
             }
             TimelineObjectResolvingStatus::InProgress(progress) => {
-                *locked_result = TimelineObjectResolvingStatus::Complete(TimelineObjectResolved {
-                    is_self_referencing: progress.is_self_referencing,
+                let is_self_referencing = progress.is_self_referencing;
+                obj.resolved = TimelineObjectResolvingStatus::Complete(TimelineObjectResolved {
+                    is_self_referencing,
 
                     instances,
                     direct_references,
+                    priority_segments,
+                });
+            }
+        }
+
+        // The children of an exclusive group are capped before anything outside of the group gets to see them
+        self.apply_exclusive_group(&info)
+    }
+}
+
+/** What became of the instances of an object in an exclusive group: None if removed, otherwise the new start and end */
+type InstanceChanges = HashMap<String, Option<(Time, Option<Time>)>>;
+
+impl<'a> ResolverContext<'a> {
+    /** If the object is in an exclusive group, resolve the rest of the group and cap the instances of its children,
+     * so that only one of them plays at a time. The children see each other uncapped while the group is being resolved
+     */
+    fn apply_exclusive_group(&mut self, info: &TimelineObjectInfo) -> Result<(), ResolveError> {
+        let group_index = info
+            .parent_id
+            .as_ref()
+            .and_then(|parent_id| self.objects.index_of(parent_id));
+        let group_index = match group_index {
+            Some(group_index) => group_index,
+            None => return Ok(()),
+        };
+        let group = self.objects.get(group_index).info.clone();
+        if group.group_mode != GroupMode::Exclusive || !self.exclusive_groups.insert(group_index) {
+            return Ok(());
+        }
+
+        let group_children = self.children.get(&group.id).cloned().unwrap_or_default();
+        for child_index in &group_children {
+            self.resolve_object(*child_index)?;
+        }
+
+        let changes = self.cap_exclusive_children(&group_children);
+        for (child_index, child_changes) in changes {
+            self.apply_instance_changes(child_index, &child_changes);
+        }
+        Ok(())
+    }
+
+    fn cap_exclusive_children(
+        &self,
+        child_indices: &[ObjectIndex],
+    ) -> Vec<(ObjectIndex, InstanceChanges)> {
+        let mut candidates = Vec::new();
+        for child_index in child_indices {
+            let child = self.objects.get(*child_index);
+            if child.info.is_keyframe || child.info.disabled {
+                continue;
+            }
+            if let TimelineObjectResolvingStatus::Complete(resolved) = &child.resolved {
+                for instance in &resolved.instances {
+                    candidates.push((
+                        *child_index,
+                        ResolvedTimelineObjectInstance {
+                            info: child.info.clone(),
+                            instance: instance.clone(),
+                            priority_segments: resolved.priority_segments.clone(),
+                        },
+                    ));
+                }
+            }
+        }
+
+        let mut times: Vec<Time> = candidates
+            .iter()
+            .flat_map(|(_, c)| vec![Some(c.instance.start), c.instance.end])
+            .flatten()
+            .collect();
+        times.sort_unstable();
+        times.dedup();
+
+        // The time each candidate started and ended playing
+        let mut played: Vec<(Option<Time>, Option<Time>)> = vec![(None, None); candidates.len()];
+        let mut playing: Option<usize> = None;
+        for time in times {
+            let winner = candidates
+                .iter()
+                .enumerate()
+                .filter(|(i, (_, c))| {
+                    // Once ended, an instance doesn't come back
+                    played[*i].1.is_none()
+                        && c.instance.start <= time
+                        && c.instance.end.unwrap_or(Time::MAX) > time
+                })
+                .min_by(|(_, (_, a)), (_, (_, b))| compare_aspiring_instances(a, b, time))
+                .map(|(i, _)| i);
+
+            if winner != playing {
+                if let Some(previous) = playing {
+                    played[previous].1 = Some(time);
+                }
+                if let Some(winner) = winner {
+                    played[winner].0 = Some(time);
+                }
+                playing = winner;
+            }
+        }
+
+        let mut changes: Vec<(ObjectIndex, InstanceChanges)> = child_indices
+            .iter()
+            .map(|index| (*index, HashMap::new()))
+            .collect();
+        for ((child_index, candidate), (start, end)) in candidates.into_iter().zip(played) {
+            let change = start.map(|start| (start, end.or(candidate.instance.end)));
+            if change != Some((candidate.instance.start, candidate.instance.end)) {
+                if let Some((_, child_changes)) =
+                    changes.iter_mut().find(|(index, _)| *index == child_index)
+                {
+                    child_changes.insert(candidate.instance.id, change);
+                }
+            }
+        }
+        changes.retain(|(_, child_changes)| !child_changes.is_empty());
+        changes
+    }
+
+    /** Change the instances of an object, and cap the instances of its children and keyframes to the changed ones */
+    fn apply_instance_changes(&mut self, index: ObjectIndex, changes: &InstanceChanges) {
+        let obj = self.objects.get_mut(index);
+        let id = obj.info.id.clone();
+        if let TimelineObjectResolvingStatus::Complete(resolved) = &mut obj.resolved {
+            resolved
+                .instances
+                .retain_mut(|instance| match changes.get(&instance.id) {
+                    None => true,
+                    Some(None) => false,
+                    Some(Some((start, end))) => {
+                        if *start != instance.start {
+                            set_instance_start_time(instance, *start);
+                        }
+                        if *end != instance.end {
+                            if let Some(end) = end {
+                                set_instance_end_time(instance, *end);
+                            }
+                        }
+                        true
+                    }
                 });
+        }
+
+        for child_index in self.children.get(&id).cloned().unwrap_or_default() {
+            let mut child_changes = InstanceChanges::new();
+            let child = self.objects.get(child_index);
+            let outlives_parent = child.info.outlives_parent;
+            if let TimelineObjectResolvingStatus::Complete(resolved) = &child.resolved {
+                for instance in &resolved.instances {
+                    let cap = instance.caps.iter().find_map(|cap| changes.get(&cap.id));
+                    if let Some(cap) = cap {
+                        let change = cap.and_then(|(cap_start, cap_end)| {
+                            let start = instance.start.max(cap_start);
+                            let end = match (instance.end, cap_end) {
+                                (end, _) if outlives_parent => end,
+                                (Some(end), Some(cap_end)) => Some(end.min(cap_end)),
+                                (end, cap_end) => end.or(cap_end),
+                            };
+                            // Even a child that outlives its parent has to start while the parent plays
+                            if end.unwrap_or(Time::MAX) > start
+                                && cap_end.unwrap_or(Time::MAX) > start
+                            {
+                                Some((start, end))
+                            } else {
+                                None
+                            }
+                        });
+                        child_changes.insert(instance.id.clone(), change);
+                    }
+                }
+            }
+            if !child_changes.is_empty() {
+                self.apply_instance_changes(child_index, &child_changes);
+            }
+        }
+    }
+}
+
+/** Cap an instance of a child into an instance of its parent, and note the cap in the instance.
+ * The end is left alone if the child outlives its parent, which the cap reflects by not having an end
+ */
+fn cap_to_parent(
+    instance: &TimelineObjectInstance,
+    parent_instance: &TimelineObjectInstance,
+    info: &TimelineObjectInfo,
+) -> Option<TimelineObjectInstance> {
+    let (capped_instance, cap_end) = if info.outlives_parent {
+        (cap_instance_start(instance, parent_instance), None)
+    } else {
+        (
+            cap_instance(instance, &[parent_instance]),
+            parent_instance.end,
+        )
+    };
+
+    capped_instance.map(|mut capped_instance| {
+        capped_instance.caps.push(Cap {
+            id: parent_instance.id.clone(),
+            start: parent_instance.start,
+            end: cap_end,
+        });
+        capped_instance
+    })
+}
 
-                Ok(())
+/** Push the end of the instance back by the pauses that start while it plays, but keep it within the caps of its parents */
+fn apply_pauses(instance: &mut TimelineObjectInstance, pauses: &[TimelinePause]) {
+    if let Some(mut end) = instance.end {
+        for pause in pauses {
+            if pause.start >= instance.start && pause.start < end {
+                end += pause.duration;
+            }
+        }
+        for cap in &instance.caps {
+            if let Some(cap_end) = cap.end {
+                end = min(end, cap_end);
             }
         }
+        instance.end = Some(end);
+    }
+}
+
+/** Convert a time in the clock of a group playing at the given rate into absolute time */
+fn scale_time(value: Time, rate: f64) -> Time {
+    if rate == 1.0 {
+        value
+    } else {
+        (value as f64 / rate).round() as Time
+    }
+}
+
+fn scale_time_ref(time_ref: TimeWithReference, rate: f64) -> TimeWithReference {
+    TimeWithReference {
+        value: scale_time(time_ref.value, rate),
+        references: time_ref.references,
+    }
+}
+
+fn scale_lookup_result(
+    result: LookupExpressionResultType,
+    rate: f64,
+) -> LookupExpressionResultType {
+    match result {
+        LookupExpressionResultType::TimeRef(time_ref) => {
+            LookupExpressionResultType::TimeRef(scale_time_ref(time_ref, rate))
+        }
+        result => result,
     }
 }
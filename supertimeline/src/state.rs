@@ -1,17 +1,26 @@
 use crate::api::ResolvedTimeline;
+use crate::instance::ResolvedPrioritySegment;
 use crate::instance::TimelineObjectInfo;
 use crate::instance::TimelineObjectInstance;
 use crate::instance::TimelineObjectResolved;
+use crate::instance::TimelinePause;
 use crate::util::set_instance_end_time;
 use crate::util::Time;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
+use std::cmp::min;
 use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::ops::Deref;
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::ops::Bound;
 use std::rc::Rc;
-use std::sync::Mutex;
+use std::sync::Arc;
 use velcro::hash_map;
 
 #[derive(PartialEq, Debug, Clone, PartialOrd)]
@@ -20,6 +29,8 @@ pub enum EventType {
     Start = 0,
     End = 1,
     KeyFrame = 2,
+    /** The transition of an object from the one it replaced ends */
+    TransitionEnd = 3,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -30,22 +41,53 @@ pub struct NextEvent {
     pub object_id: String,
 }
 
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct ResolvedTimelineObject {
     pub resolved: TimelineObjectResolved,
-    pub info: Rc<TimelineObjectInfo>,
+    pub info: Arc<TimelineObjectInfo>,
 }
 
-pub type AllStates = HashMap<String, HashMap<Time, Option<TimelineLayerState>>>;
+/** The changes of each layer, ordered by the time they happen at. None means the layer becomes empty */
+pub type AllStates = HashMap<String, BTreeMap<Time, Option<TimelineLayerState>>>;
 
-#[derive(Clone)]
+/** The changes of each multi-slot layer, ordered by the time they happen at. Each change contains the objects on the layer, in the order of the layer */
+pub type AllSlotStates = HashMap<String, BTreeMap<Time, Vec<TimelineLayerState>>>;
+
+/** Makes a layer hold several objects at the same time, instead of only the one with the highest precedence */
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct LayerSlots {
+    /** The max number of objects on the layer. The ones with the highest precedence are chosen. None means all the active objects */
+    pub capacity: Option<usize>,
+    /** The order of the objects on the layer */
+    pub order: SlotOrder,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum SlotOrder {
+    /** In order of precedence, the same as is used to determine what is on a normal layer */
+    #[default]
+    Precedence,
+    /** In the order the objects started, so that objects keep their position when others start or end */
+    Start,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct ResolvedStatesForObject {
-    pub info: Rc<TimelineObjectInfo>,
-    pub instances: HashMap<String, Rc<Mutex<TimelineObjectInstance>>>,
+    pub info: Arc<TimelineObjectInfo>,
+    pub instances: HashMap<String, TimelineObjectInstance>,
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct ResolvedStates {
     pub state: AllStates,
+    /** The states of the multi-slot layers, which are not included in state */
+    pub slots: AllSlotStates,
+    /** Sorted by time */
     pub next_events: Vec<NextEvent>,
 
     // TODO - some of these below are excessive and need clarifying what they now are
@@ -55,42 +97,155 @@ pub struct ResolvedStates {
     // pub classes: HashMap<String, Vec<String>>,
     /** Map of the object ids, per layer */
     pub layers: HashMap<String, Vec<String>>,
+    /** The time after which the states may be incomplete, see ResolvedTimeline.valid_until */
+    pub valid_until: Option<Time>,
+    /** All the instances that aspired to be on each layer, including the ones covered by other objects.
+     * Sorted by start time, then by precedence on the layer
+     */
+    pub aspiring: HashMap<String, Vec<LookaheadObject>>,
+    /** The time ranges where more objects aspired to a layer than it could hold, per layer. Ordered by time */
+    pub conflicts: HashMap<String, Vec<LayerConflict>>,
 }
 
+/** A time range where some of the objects aspiring to a layer were covered by the ones on it */
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct LayerConflict {
+    pub start: Time,
+    /** None if the conflict never ends */
+    pub end: Option<Time>,
+    /** Ids of the objects on the layer */
+    pub winners: Vec<String>,
+    /** The objects that were covered */
+    pub losers: Vec<ShadowedObject>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct ShadowedObject {
+    pub object_id: String,
+    /** Why the object lost to the winner with the lowest precedence */
+    pub reason: ConflictReason,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum ConflictReason {
+    /** The winner has a higher priority */
+    Priority,
+    /** The winner has the same priority, but started later */
+    LaterStart,
+    /** The winner has the same priority and start, but comes first when sorted by id */
+    IdTieBreak,
+}
+
+/** An instance that aspires to be on a layer */
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct LookaheadObject {
+    pub object_id: String,
+    /** The instance as resolved, before being capped by other objects on the layer */
+    pub instance: TimelineObjectInstance,
+    /** True if the instance will be on the layer at some point, false if it is always covered by other objects */
+    pub plays: bool,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct ResolvedTimelineObjectInstanceKeyframe {
     // TODO - remove info from here, and surely the keyframe id will be needed instead?
-    pub info: Rc<TimelineObjectInfo>,
-    // pub instance: Rc<TimelineObjectInstance>,
+    pub info: Arc<TimelineObjectInfo>,
     pub keyframe_end_time: Option<Time>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct ResolvedTimelineObjectInstance {
-    pub info: Rc<TimelineObjectInfo>,
-    pub instance_id: String,
-    pub instance: Rc<Mutex<TimelineObjectInstance>>,
+    pub info: Arc<TimelineObjectInfo>,
+    pub instance: TimelineObjectInstance,
+    /** The times the priority segments of the object apply */
+    pub priority_segments: Vec<ResolvedPrioritySegment>,
+}
+impl ResolvedTimelineObjectInstance {
+    /** The priority of the object at the given time, from the last priority segment that applies or else .priority */
+    pub fn priority_at(&self, time: Time) -> i64 {
+        self.priority_segments
+            .iter()
+            .rfind(|segment| segment.start <= time && segment.end.unwrap_or(Time::MAX) > time)
+            .map_or(self.info.priority, |segment| segment.priority)
+    }
 }
 
+/** What is playing on a layer */
 #[derive(Debug, Clone)]
-pub struct TimelineLayerState2 {
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct TimelineLayerState {
     pub object_id: String,
-    pub instance: Rc<TimelineObjectInstance>, // TODO - this is a bit heavy now?
-    pub keyframes: Vec<Rc<ResolvedTimelineObjectInstanceKeyframe>>,
+    pub instance: TimelineObjectInstance,
+    /** The keyframes applied to the object, in the order they are to be applied */
+    pub keyframes: Vec<ResolvedTimelineObjectInstanceKeyframe>,
+    /** Set while the object is transitioning from the object it replaced, see IsTimelineObject.transition_duration */
+    pub transition: Option<LayerTransition>,
+    /** How far into its content the object is at the time of the state.
+     * Counted from the original start of the instance, so includes the time it was covered by other objects or cut off by a parent group
+     */
+    pub content_offset: Time,
+    /** How long until the object leaves the layer, at the time of the state. None if it never does */
+    pub remaining: Option<Time>,
+    /** True if the object was on the layer before, and is continuing after being covered by other objects */
+    pub resumed: bool,
+    /** The pauses of the object, see IsTimelineObject.pauses */
+    pub pauses: Vec<TimelinePause>,
+    /** True if the object is paused at the time of the state, meaning its content_offset is frozen */
+    pub paused: bool,
+    /** The rate the content of the object plays at, from the playback rates of its parent groups */
+    pub playback_rate: f64,
+}
+impl TimelineLayerState {
+    fn set_time(&mut self, time: Time) {
+        let content_start = self.instance.original_start.unwrap_or(self.instance.start);
+        self.content_offset = time.saturating_sub(content_start);
+        self.remaining = self.instance.end.map(|end| end.saturating_sub(time));
+        self.paused = false;
+
+        // The content doesn't move forward while paused
+        for pause in &self.pauses {
+            if pause.start < content_start || pause.start >= time {
+                continue;
+            }
+            let pause_end = pause.start + pause.duration;
+            self.content_offset = self
+                .content_offset
+                .saturating_sub(min(time, pause_end) - pause.start);
+            if time < pause_end {
+                self.paused = true;
+            }
+        }
+        if self.playback_rate != 1.0 {
+            self.content_offset = (self.content_offset as f64 * self.playback_rate).round() as Time;
+        }
+    }
 }
 
+/** A transition between the object that is replaced on a layer and the one replacing it */
 #[derive(Debug, Clone)]
-pub struct TimelineLayerState {
-    pub object_id: String,
-    pub instance_id: Option<String>, // TODO - these both being Option<> is horrible
-    pub instance: Option<Rc<Mutex<TimelineObjectInstance>>>, // TODO - this is a bit heavy now?
-    pub keyframes: Vec<Rc<ResolvedTimelineObjectInstanceKeyframe>>,
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct LayerTransition {
+    /** The object that is replaced, as it was when the transition started */
+    pub outgoing: Box<TimelineLayerState>,
+    pub start: Time,
+    pub end: Time,
+    /** How far the transition has come at the time of the state, from 0 to 1 */
+    pub progress: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct TimelineState {
     pub time: Time,
-    pub layers: HashMap<String, TimelineLayerState2>,
+    pub layers: HashMap<String, TimelineLayerState>,
+    /** The objects on the multi-slot layers, in the order of the layer */
+    pub slots: HashMap<String, Vec<TimelineLayerState>>,
     pub next_events: Vec<NextEvent>,
 }
 
@@ -109,10 +264,9 @@ pub fn get_state(
         usize::MAX
     };
 
-    let next_events = resolved
-        .next_events
+    let first_event = resolved.next_events.partition_point(|e| e.time <= time);
+    let next_events = resolved.next_events[first_event..]
         .iter()
-        .filter(|&e| e.time > time)
         .take(event_limit2)
         .cloned()
         .collect::<Vec<_>>();
@@ -120,76 +274,386 @@ pub fn get_state(
     let mut layers = HashMap::new();
 
     for layer_id in resolved.layers.keys() {
-        if let Some(state) = get_state_at_time_for_layer(&resolved.state, layer_id, time) {
-            layers.insert(layer_id.clone(), state);
+        if let Some(layer_states) = resolved.state.get(layer_id) {
+            if let Some(state) = get_layer_state_at_time(layer_states, time).pop() {
+                layers.insert(layer_id.clone(), state);
+            }
+        }
+    }
+
+    let mut slots = HashMap::new();
+    for (layer_id, layer_states) in &resolved.slots {
+        let states = get_layer_state_at_time(layer_states, time);
+        if !states.is_empty() {
+            slots.insert(layer_id.clone(), states);
         }
     }
 
     TimelineState {
         time,
         layers,
+        slots,
         next_events,
     }
 }
 
-fn get_state_at_time_for_layer(
-    states: &AllStates,
-    layer_id: &str,
+/** The objects on a layer, either a single state or the states of a multi-slot layer */
+pub(crate) trait LayerStates {
+    fn as_states(&self) -> &[TimelineLayerState];
+}
+impl LayerStates for Option<TimelineLayerState> {
+    fn as_states(&self) -> &[TimelineLayerState] {
+        self.as_slice()
+    }
+}
+impl LayerStates for Vec<TimelineLayerState> {
+    fn as_states(&self) -> &[TimelineLayerState] {
+        self
+    }
+}
+
+fn get_layer_state_at_time<T: LayerStates>(
+    layer_states: &BTreeMap<Time, T>,
     request_time: Time,
-) -> Option<TimelineLayerState2> {
-    if let Some(layer_states) = states.get(layer_id) {
-        let layer_contents = {
-            let mut tmp = layer_states.iter().collect::<Vec<_>>();
-            tmp.sort_by_key(|&a| a.0);
-            tmp
-        };
+) -> Vec<TimelineLayerState> {
+    // Each change contains the full state of the layer, so the last one before the time is all that is needed
+    let state = match layer_states.range(..=request_time).next_back() {
+        Some((_, state)) => state.as_states(),
+        None => &[],
+    };
 
-        let mut best_state: Option<TimelineLayerState2> = None;
+    state
+        .iter()
+        .map(|state| {
+            let mut state = state.clone();
+            state
+                .keyframes
+                .retain(|keyframe| keyframe.keyframe_end_time.unwrap_or(Time::MAX) > request_time);
+            state.transition = state
+                .transition
+                .take()
+                .filter(|transition| transition.end > request_time)
+                .map(|mut transition| {
+                    transition.progress = (request_time - transition.start) as f64
+                        / (transition.end - transition.start) as f64;
+                    transition.outgoing.set_time(request_time);
+                    transition
+                });
+            state.set_time(request_time);
+            state
+        })
+        .collect()
+}
 
-        for (time, current_state_instances) in layer_contents {
-            if *time <= request_time {
-                if let Some(current_state_instances) = current_state_instances {
-                    let no_instance = if let Some(instance) = &current_state_instances.instance {
-                        let instance2 = instance.lock().unwrap(); // TODO - eww
+/** A change of the state of a layer. On multi-slot layers, each object that starts, ends or gets other keyframes is a change of its own */
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct StateChange {
+    pub time: Time,
+    pub layer: String,
+    /** The state of the layer before the change. None if the layer was empty */
+    pub previous: Option<TimelineLayerState>,
+    /** The state of the layer after the change. None if the layer becomes empty */
+    pub current: Option<TimelineLayerState>,
+    /** The keyframes that started being applied */
+    pub keyframes_added: Vec<ResolvedTimelineObjectInstanceKeyframe>,
+    /** The keyframes that stopped being applied */
+    pub keyframes_removed: Vec<ResolvedTimelineObjectInstanceKeyframe>,
+}
 
-                        best_state = Some(TimelineLayerState2 {
-                            object_id: current_state_instances.object_id.clone(),
-                            instance: Rc::new((*instance2).clone()),
-                            keyframes: Vec::new(),
-                        });
-                        false
-                    } else {
-                        true
-                    };
+impl ResolvedStates {
+    /** The next objects starting after the time on each layer, at most count per layer */
+    pub fn lookahead(&self, time: Time, count: usize) -> HashMap<String, Vec<LookaheadObject>> {
+        let mut lookahead = HashMap::new();
+        for (layer, aspiring) in &self.aspiring {
+            let first = aspiring.partition_point(|obj| obj.instance.start <= time);
+            let upcoming = aspiring[first..]
+                .iter()
+                .take(count)
+                .cloned()
+                .collect::<Vec<_>>();
+            if !upcoming.is_empty() {
+                lookahead.insert(layer.clone(), upcoming);
+            }
+        }
+        lookahead
+    }
 
-                    if current_state_instances.keyframes.is_empty() && no_instance {
-                        best_state = None;
-                    } else if let Some(current_state) = &mut best_state {
-                        let mut keyframes = current_state_instances.keyframes.clone();
-                        keyframes.retain(|keyframe| {
-                            if let Some(parent_id) = &keyframe.info.parent_id {
-                                if parent_id.eq(&current_state.object_id)
-                                    && keyframe.keyframe_end_time.unwrap_or(Time::MAX)
-                                        > request_time
-                                {
-                                    // Apply the keyframe on the state:
-                                    return true;
-                                }
-                            }
-                            false
-                        });
+    /** The changes of the layers from (inclusive) until (exclusive) the given times, in chronological order.
+     * The changes are found while iterating, so only the part of the timeline that is iterated over is looked at
+     */
+    pub fn changes(&self, from: Time, to: Time) -> impl Iterator<Item = StateChange> + '_ {
+        let mut layers: Vec<Box<dyn Iterator<Item = StateChange> + '_>> = Vec::new();
+
+        if from < to {
+            for (layer, layer_states) in &self.state {
+                layers.push(Box::new(LayerStateChanges::new(
+                    layer,
+                    layer_states,
+                    from,
+                    to,
+                )));
+            }
+            for (layer, layer_states) in &self.slots {
+                layers.push(Box::new(LayerStateChanges::new(
+                    layer,
+                    layer_states,
+                    from,
+                    to,
+                )));
+            }
+        }
+
+        MergedChanges::new(layers)
+    }
+}
+
+/** The changes of a single layer, in chronological order */
+struct LayerStateChanges<'a, T: LayerStates> {
+    layer: &'a str,
+    layer_states: &'a BTreeMap<Time, T>,
+    /** The change points of the layer that are still to be looked at */
+    states: Peekable<btree_map::Range<'a, Time, T>>,
+    /** The ends of the keyframes seen so far that are still to be looked at */
+    keyframe_ends: BinaryHeap<Reverse<Time>>,
+    from: Time,
+    to: Time,
+    last_time: Option<Time>,
+    previous: Vec<TimelineLayerState>,
+    pending: VecDeque<StateChange>,
+}
+
+impl<'a, T: LayerStates> LayerStateChanges<'a, T> {
+    fn new(
+        layer: &'a str,
+        layer_states: &'a BTreeMap<Time, T>,
+        from: Time,
+        to: Time,
+    ) -> LayerStateChanges<'a, T> {
+        let mut layer_changes = LayerStateChanges {
+            layer,
+            layer_states,
+            states: layer_states.range(from..to).peekable(),
+            keyframe_ends: BinaryHeap::new(),
+            from,
+            to,
+            last_time: None,
+            previous: Vec::new(),
+            pending: VecDeque::new(),
+        };
+        // The state active at the start can have keyframes ending in the range
+        if let Some((_, state)) = layer_states.range(..from).next_back() {
+            layer_changes.add_keyframe_ends(state);
+        }
+        layer_changes
+    }
+
+    fn add_keyframe_ends(&mut self, state: &T) {
+        let (from, to) = (self.from, self.to);
+        for state in state.as_states() {
+            self.keyframe_ends.extend(
+                state
+                    .keyframes
+                    .iter()
+                    .filter_map(|keyframe| keyframe.keyframe_end_time)
+                    .filter(|end| *end >= from && *end < to)
+                    .map(Reverse),
+            );
+        }
+    }
 
-                        current_state.keyframes.extend(keyframes);
+    /** The layer can change at its own change points, or when a keyframe ends */
+    fn next_time(&mut self) -> Option<Time> {
+        loop {
+            let next_state = self.states.peek().map(|(time, _)| **time);
+            let next_keyframe_end = self.keyframe_ends.peek().map(|Reverse(time)| *time);
+            let time = match (next_state, next_keyframe_end) {
+                (None, None) => return None,
+                (Some(state_time), end) if end.is_none_or(|end| state_time <= end) => {
+                    if let Some((_, state)) = self.states.next() {
+                        self.add_keyframe_ends(state);
                     }
-                } else {
-                    best_state = None;
+                    state_time
                 }
-            } else {
+                _ => {
+                    self.keyframe_ends.pop();
+                    next_keyframe_end?
+                }
+            };
+
+            if self.last_time.is_none_or(|last_time| time > last_time) {
+                return Some(time);
+            }
+        }
+    }
+
+    fn add_changes_at(&mut self, time: Time) {
+        if self.last_time.is_none() && time > 0 {
+            self.previous = get_layer_state_at_time(self.layer_states, time - 1);
+        }
+        self.last_time = Some(time);
+
+        let layer = self.layer;
+        let previous = std::mem::take(&mut self.previous);
+        let current = get_layer_state_at_time(self.layer_states, time);
+
+        let is_same = |a: &TimelineLayerState, b: &TimelineLayerState| {
+            a.object_id == b.object_id && a.instance.id == b.instance.id
+        };
+        let mut unmatched_previous = previous
+            .iter()
+            .filter(|p| !current.iter().any(|c| is_same(p, c)))
+            .cloned();
+        let mut unmatched_current = current
+            .iter()
+            .filter(|c| !previous.iter().any(|p| is_same(p, c)))
+            .cloned();
+
+        // The objects that stay on the layer can only have changed keyframes
+        for state in current.iter() {
+            if let Some(previous_state) = previous.iter().find(|p| is_same(p, state)) {
+                self.pending.extend(diff_layer_states(
+                    time,
+                    layer,
+                    Some(previous_state.clone()),
+                    Some(state.clone()),
+                ));
+            }
+        }
+        // The others have replaced each other, started or ended
+        loop {
+            let previous_state = unmatched_previous.next();
+            let current_state = unmatched_current.next();
+            if previous_state.is_none() && current_state.is_none() {
                 break;
             }
+            self.pending.extend(diff_layer_states(
+                time,
+                layer,
+                previous_state,
+                current_state,
+            ));
+        }
+
+        self.previous = current;
+    }
+}
+
+impl<'a, T: LayerStates> Iterator for LayerStateChanges<'a, T> {
+    type Item = StateChange;
+
+    fn next(&mut self) -> Option<StateChange> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(change);
+            }
+            let time = self.next_time()?;
+            self.add_changes_at(time);
+        }
+    }
+}
+
+/** Merges the changes of the layers, ordered by time and then by layer */
+struct MergedChanges<'a> {
+    layers: Vec<Box<dyn Iterator<Item = StateChange> + 'a>>,
+    /** The next change of each layer */
+    heads: Vec<Option<StateChange>>,
+    order: BinaryHeap<Reverse<(Time, String, usize)>>,
+}
+
+impl<'a> MergedChanges<'a> {
+    fn new(mut layers: Vec<Box<dyn Iterator<Item = StateChange> + 'a>>) -> MergedChanges<'a> {
+        let heads = layers
+            .iter_mut()
+            .map(|layer| layer.next())
+            .collect::<Vec<_>>();
+        let order = heads
+            .iter()
+            .enumerate()
+            .filter_map(|(index, head)| {
+                head.as_ref()
+                    .map(|change| Reverse((change.time, change.layer.clone(), index)))
+            })
+            .collect();
+        MergedChanges {
+            layers,
+            heads,
+            order,
+        }
+    }
+}
+
+impl<'a> Iterator for MergedChanges<'a> {
+    type Item = StateChange;
+
+    fn next(&mut self) -> Option<StateChange> {
+        let Reverse((_, _, index)) = self.order.pop()?;
+        let change = self.heads[index].take();
+
+        self.heads[index] = self.layers[index].next();
+        if let Some(next) = &self.heads[index] {
+            self.order
+                .push(Reverse((next.time, next.layer.clone(), index)));
         }
 
-        best_state
+        change
+    }
+}
+
+fn diff_layer_states(
+    time: Time,
+    layer: &str,
+    previous: Option<TimelineLayerState>,
+    current: Option<TimelineLayerState>,
+) -> Option<StateChange> {
+    let keyframes = |state: &Option<TimelineLayerState>| {
+        state
+            .as_ref()
+            .map(|state| state.keyframes.clone())
+            .unwrap_or_default()
+    };
+    let previous_keyframes = keyframes(&previous);
+    let current_keyframes = keyframes(&current);
+
+    let contains = |keyframes: &Vec<ResolvedTimelineObjectInstanceKeyframe>,
+                    keyframe: &ResolvedTimelineObjectInstanceKeyframe| {
+        keyframes.iter().any(|k| k.info.id == keyframe.info.id)
+    };
+    let keyframes_added = current_keyframes
+        .iter()
+        .filter(|keyframe| !contains(&previous_keyframes, keyframe))
+        .cloned()
+        .collect::<Vec<_>>();
+    let keyframes_removed = previous_keyframes
+        .iter()
+        .filter(|keyframe| !contains(&current_keyframes, keyframe))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let instance_id = |state: &Option<TimelineLayerState>| {
+        state
+            .as_ref()
+            .map(|state| (state.object_id.clone(), state.instance.id.clone()))
+    };
+    let transition_end = |state: &Option<TimelineLayerState>| {
+        state
+            .as_ref()
+            .and_then(|state| state.transition.as_ref())
+            .map(|transition| transition.end)
+    };
+    if instance_id(&previous) != instance_id(&current)
+        || transition_end(&previous) != transition_end(&current)
+        || !keyframes_added.is_empty()
+        || !keyframes_removed.is_empty()
+    {
+        Some(StateChange {
+            time,
+            layer: layer.to_string(),
+            previous,
+            current,
+            keyframes_added,
+            keyframes_removed,
+        })
     } else {
         None
     }
@@ -212,6 +676,15 @@ struct PointInTime {
 pub fn resolve_all_states(
     resolved: &ResolvedTimeline,
     only_for_time: Option<Time>,
+) -> Result<ResolvedStates, ResolvedStatesError> {
+    resolve_all_states_with_slots(resolved, only_for_time, &HashMap::new())
+}
+
+/** Like resolve_all_states, but the given layers are multi-slot layers, which can hold several objects at the same time */
+pub fn resolve_all_states_with_slots(
+    resolved: &ResolvedTimeline,
+    only_for_time: Option<Time>,
+    layer_slots: &HashMap<String, LayerSlots>,
 ) -> Result<ResolvedStates, ResolvedStatesError> {
     // if (
     // 	cache &&
@@ -299,6 +772,15 @@ pub fn resolve_all_states(
                         }
                     }
 
+                    // And the times the priority changes, as the object may win or lose its layer then:
+                    for segment in &obj.resolved.priority_segments {
+                        for time in vec![Some(segment.start), segment.end].into_iter().flatten() {
+                            if time > instance.start && time < instance.end.unwrap_or(Time::MAX) {
+                                time_events.push(TimeEvent { time, enable: true })
+                            }
+                        }
+                    }
+
                     let inner_obj = make_resolved_obj(obj, instance);
 
                     // Save a reference to this instance on all points in time that could affect it:
@@ -337,7 +819,9 @@ pub fn resolve_all_states(
     // determining the state for every point in time by adding & removing objects from aspiringInstances
     // Then sorting it to determine who takes precedence
 
-    let mut current_state: HashMap<String, Rc<ResolvedTimelineObjectInstance>> = HashMap::new();
+    let mut current_state: HashMap<String, Vec<Rc<ResolvedTimelineObjectInstance>>> =
+        HashMap::new();
+    let mut layer_changes: LayerChanges = HashMap::new();
     let mut active_object_ids = HashMap::new();
     let mut active_keyframes = HashMap::new();
     let mut active_keyframes_checked = HashSet::new();
@@ -347,17 +831,30 @@ pub fn resolve_all_states(
     let mut resolved_states = ResolvedStates {
         // timeline: (),
         state: HashMap::new(),
+        slots: HashMap::new(),
         next_events: Vec::new(),
 
         objects: HashMap::new(),
         layers: HashMap::new(),
         // classes: HashMap::new(),
+        valid_until: resolved.valid_until,
+        aspiring: HashMap::new(),
+        conflicts: HashMap::new(),
     };
 
     // /** The objects in aspiringInstances  */
     let mut aspiring_instances: HashMap<String, Vec<Rc<ResolvedTimelineObjectInstance>>> =
         HashMap::new();
 
+    // Every instance that has been in aspiringInstances, and whether it has been on top of its layer
+    let mut aspired_instances: HashMap<
+        (String, String),
+        (Rc<ResolvedTimelineObjectInstance>, bool),
+    > = HashMap::new();
+
+    // The conflicts on each layer that have not ended yet
+    let mut open_conflicts: HashMap<String, LayerConflict> = HashMap::new();
+
     let mut keyframe_events: Vec<NextEvent> = Vec::new();
 
     let sorted_points_in_time = {
@@ -404,10 +901,7 @@ pub fn resolve_all_states(
 
         for o in instances_to_check2 {
             let obj = &o.obj;
-            let instance = {
-                let a = o.obj.instance.try_lock().unwrap(); // TODO - eww
-                a.clone() // Clone to avoid holding a lock
-            };
+            let instance = &o.obj.instance;
 
             let to_be_enabled = instance.start <= time && instance.end.unwrap_or(Time::MAX) > time;
 
@@ -423,8 +917,19 @@ pub fn resolve_all_states(
                             .as_ref()
                             .and_then(|parent_id| resolved.objects.get(parent_id))
                         {
+                            // A child that outlives its parent only needs the parent to be active when it starts
+                            let already_started = obj.info.outlives_parent
+                                && aspiring_instances.get(&obj.info.layer).is_some_and(
+                                    |aspiring| {
+                                        aspiring.iter().any(|i| {
+                                            i.info.id.eq(&obj.info.id)
+                                                && i.instance.id.eq(&instance.id)
+                                        })
+                                    },
+                                );
                             parent_obj.info.layer.is_empty()
                                 || active_object_ids.contains_key(&parent_obj.info.id)
+                                || already_started
                         } else {
                             to_be_enabled
                         }
@@ -440,39 +945,18 @@ pub fn resolve_all_states(
                         // The instance wants to be enabled (is starting)
 
                         // Add to aspiringInstances:
-                        layer_aspiring_instances.push(o.obj.clone());
-
-                        layer_aspiring_instances.sort_by(|a, b| {
-                            // Determine who takes precedence:
-
-                            // First, sort using priority
-                            if a.info.priority < b.info.priority {
-                                return Ordering::Greater;
-                            }
-                            if a.info.priority > b.info.priority {
-                                return Ordering::Less;
-                            }
-
-                            let a_start = {
-                                let instance = a.instance.try_lock().unwrap(); // TODO - eww
-                                instance.start
-                            };
-                            let b_start = {
-                                let instance = b.instance.try_lock().unwrap(); // TODO - eww
-                                instance.start
-                            };
-
-                            // Then, sort using the start time
-                            if a_start < b_start {
-                                return Ordering::Greater;
-                            }
-                            if a_start > b_start {
-                                return Ordering::Less;
-                            }
+                        if !layer_aspiring_instances
+                            .iter()
+                            .any(|i| i.info.id.eq(&obj.info.id) && i.instance.id.eq(&instance.id))
+                        {
+                            layer_aspiring_instances.push(o.obj.clone());
+                        }
+                        aspired_instances
+                            .entry((obj.info.id.clone(), instance.id.clone()))
+                            .or_insert_with(|| (o.obj.clone(), false));
 
-                            // Last resort: sort using id:
-                            a.info.id.cmp(&b.info.id)
-                        });
+                        layer_aspiring_instances
+                            .sort_by(|a, b| compare_aspiring_instances(a, b, time));
                     } else {
                         // The instance doesn't want to be enabled (is ending)
 
@@ -480,32 +964,47 @@ pub fn resolve_all_states(
                         layer_aspiring_instances.retain(|i| !i.info.id.eq(&obj.info.id));
                     }
 
-                    // Now, the one on top has the throne
+                    // Now, the ones on top have the throne
                     // Update current state:
-                    let new_obj_on_layer = layer_aspiring_instances.first();
-                    let prev_obj_on_layer = current_state.get(&obj.info.layer);
-
-                    let replace_old_obj = if let Some(current_on_top_of_layer) = new_obj_on_layer {
-                        if let Some(prev_obj) = prev_obj_on_layer {
-                            !prev_obj.info.id.eq(&current_on_top_of_layer.info.id)
-                                || !prev_obj
-                                    .instance_id
-                                    .eq(&current_on_top_of_layer.instance_id)
-                        } else {
-                            true
-                        }
-                    } else {
-                        false
+                    let new_objs_on_layer = select_layer_occupants(
+                        layer_aspiring_instances,
+                        layer_slots.get(&obj.info.layer),
+                    );
+                    let prev_objs_on_layer =
+                        current_state.remove(&obj.info.layer).unwrap_or_default();
+
+                    // Keep track of the objects that are covered by the ones on the layer
+                    update_layer_conflict(
+                        &mut resolved_states.conflicts,
+                        &mut open_conflicts,
+                        &obj.info.layer,
+                        time,
+                        layer_aspiring_instances,
+                        &new_objs_on_layer,
+                    );
+
+                    // The objects on the layer are new instances, made from the aspiring ones
+                    let is_same = |prev_obj: &Rc<ResolvedTimelineObjectInstance>,
+                                   aspiring_obj: &Rc<ResolvedTimelineObjectInstance>| {
+                        prev_obj.info.id.eq(&aspiring_obj.info.id)
+                            && prev_obj.instance.from_instance_id.as_ref()
+                                == Some(&aspiring_obj.instance.id)
                     };
-                    let remove_old_obj = prev_obj_on_layer.is_some() && new_obj_on_layer.is_none();
 
-                    if replace_old_obj || remove_old_obj {
-                        if let Some(prev_obj_on_layer) = prev_obj_on_layer {
+                    for prev_obj_on_layer in &prev_objs_on_layer {
+                        if !new_objs_on_layer
+                            .iter()
+                            .any(|new_obj| is_same(prev_obj_on_layer, new_obj))
+                        {
                             // Cap the old instance, so it'll end at this point in time:
+                            if let Some(prev_obj_on_layer_instance) = resolved_states
+                                .objects
+                                .get_mut(&prev_obj_on_layer.info.id)
+                                .and_then(|obj| {
+                                    obj.instances.get_mut(&prev_obj_on_layer.instance.id)
+                                })
                             {
-                                let mut prev_obj_on_layer_instance =
-                                    prev_obj_on_layer.instance.try_lock().unwrap(); // TODO - eww
-                                set_instance_end_time(&mut prev_obj_on_layer_instance, time);
+                                set_instance_end_time(prev_obj_on_layer_instance, time);
                             }
 
                             // Update activeObjIds:
@@ -526,10 +1025,25 @@ pub fn resolve_all_states(
                             }
                         }
                     }
-                    if replace_old_obj {
-                        // Set the new object to State
 
-                        let current_on_top_of_layer = new_obj_on_layer.unwrap(); // TODO - eww
+                    let mut objs_on_layer = Vec::new();
+                    for current_on_top_of_layer in &new_objs_on_layer {
+                        if let Some(prev_obj) = prev_objs_on_layer
+                            .iter()
+                            .find(|prev_obj| is_same(prev_obj, current_on_top_of_layer))
+                        {
+                            // Still on the layer
+                            objs_on_layer.push(prev_obj.clone());
+                            continue;
+                        }
+
+                        // Set the new object to State
+                        if let Some(aspired) = aspired_instances.get_mut(&(
+                            current_on_top_of_layer.info.id.clone(),
+                            current_on_top_of_layer.instance.id.clone(),
+                        )) {
+                            aspired.1 = true;
+                        }
 
                         // Construct a new object clone:
                         let new_obj = {
@@ -579,9 +1093,9 @@ pub fn resolve_all_states(
                             }
                         };
 
-                        let (new_instance, new_instance_id, new_instance_start) = {
-                            let raw_instance = current_on_top_of_layer.instance.try_lock().unwrap(); // TODO - eww
-                            let mut new_instance = raw_instance.deref().clone();
+                        let new_instance = {
+                            let raw_instance = &current_on_top_of_layer.instance;
+                            let mut new_instance = raw_instance.clone();
                             // We're setting new start & end times so they match up with the state:
                             new_instance.start = time;
                             new_instance.end = None;
@@ -602,51 +1116,54 @@ pub fn resolve_all_states(
                                 }
                             }
 
-                            let id = new_instance.id.clone();
-                            let start = new_instance.start;
-
-                            (Rc::new(Mutex::new(new_instance)), id, start)
+                            new_instance
                         };
+                        let new_instance_start = new_instance.start;
                         new_obj
                             .instances
-                            .insert(new_instance_id.clone(), new_instance.clone());
+                            .insert(new_instance.id.clone(), new_instance.clone());
 
                         let new_obj_instance = Rc::new(ResolvedTimelineObjectInstance {
                             info: new_obj.info.clone(),
-                            instance_id: new_instance_id.clone(),
-                            instance: new_instance.clone(),
+                            instance: new_instance,
+                            priority_segments: current_on_top_of_layer.priority_segments.clone(),
                         });
 
-                        // Save to current state:
-                        current_state.insert(new_obj.info.layer.clone(), new_obj_instance.clone());
-
                         // Update activeObjIds:
                         active_object_ids
                             .insert(new_obj_instance.info.id.clone(), new_obj_instance.clone());
 
-                        // Update the tracking state as well:
-                        set_state_at_time(
-                            &mut resolved_states.state,
-                            &new_obj.info.layer,
-                            time,
-                            Some(&new_obj_instance),
-                        );
-
                         // Add to nextEvents:
                         if new_instance_start > only_for_time.unwrap_or(0) {
                             resolved_states.next_events.push(NextEvent {
                                 event_type: EventType::Start,
                                 time: new_instance_start,
-                                object_id: obj.info.id.clone(),
+                                object_id: new_obj_instance.info.id.clone(),
                             });
                             event_object_times.insert(new_instance_start);
                         }
-                    } else if remove_old_obj {
-                        // Remove from current state:
-                        current_state.remove(&obj.info.layer);
 
+                        objs_on_layer.push(new_obj_instance);
+                    }
+
+                    let layer_changed = objs_on_layer.len() != prev_objs_on_layer.len()
+                        || objs_on_layer
+                            .iter()
+                            .zip(prev_objs_on_layer.iter())
+                            .any(|(a, b)| !Rc::ptr_eq(a, b));
+                    if layer_changed {
                         // Update the tracking state as well:
-                        set_state_at_time(&mut resolved_states.state, &obj.info.layer, time, None);
+                        set_state_at_time(
+                            &mut layer_changes,
+                            &obj.info.layer,
+                            time,
+                            &objs_on_layer,
+                        );
+                    }
+
+                    // Save to current state:
+                    if !objs_on_layer.is_empty() {
+                        current_state.insert(obj.info.layer.clone(), objs_on_layer);
                     }
                 } else {
                     // Is a keyframe
@@ -656,7 +1173,7 @@ pub fn resolve_all_states(
                         obj.info.id.clone(),
                         ResolvedStatesForObject {
                             info: obj.info.clone(),
-                            instances: hash_map! {obj.instance_id.clone(): obj.instance.clone()},
+                            instances: hash_map! {obj.instance.id.clone(): obj.instance.clone()},
                         },
                     );
 
@@ -670,12 +1187,12 @@ pub fn resolve_all_states(
             }
         }
 
-        for (obj_id, obj_instance) in &active_keyframes {
-            let keyframe = &obj_instance;
-            let instance = {
-                let a = keyframe.instance.try_lock().unwrap(); // TODO - eww
-                a.clone()
-            };
+        // Sorted, so that keyframes starting at the same time are always applied in the same order
+        let mut active_keyframe_ids = active_keyframes.keys().collect::<Vec<_>>();
+        active_keyframe_ids.sort();
+        for obj_id in active_keyframe_ids {
+            let keyframe = &active_keyframes[obj_id];
+            let instance = &keyframe.instance;
 
             let mut unhandled = true;
 
@@ -687,23 +1204,25 @@ pub fn resolve_all_states(
             if let Some(parent_obj) = parent_obj {
                 if !parent_obj.info.layer.is_empty() {
                     // keyframe is on an active object
-                    // TODO - how do we know that parent_obj and parent_obj_instance are related? there is no check here
-                    if let Some(parent_obj_instance) = current_state.get(&parent_obj.info.layer) {
+                    let parent_obj_instance =
+                        current_state.get(&parent_obj.info.layer).and_then(|objs| {
+                            objs.iter().find(|obj| obj.info.id.eq(&parent_obj.info.id))
+                        });
+                    if let Some(parent_obj_instance) = parent_obj_instance {
+                        unhandled = false;
                         if active_keyframes_checked.insert(obj_id.clone()) {
                             // hasn't started before
-                            let keyframe_instance =
-                                Rc::new(ResolvedTimelineObjectInstanceKeyframe {
-                                    info: keyframe.info.clone(),
-                                    // instance: instance.clone(),
-                                    keyframe_end_time: instance.end,
-                                });
+                            let keyframe_instance = ResolvedTimelineObjectInstanceKeyframe {
+                                info: keyframe.info.clone(),
+                                keyframe_end_time: instance.end,
+                            };
 
                             // Add keyframe to the tracking state:
                             add_keyframe_at_time(
-                                &mut resolved_states.state,
+                                &mut layer_changes,
                                 &parent_obj.info.layer,
                                 time,
-                                &keyframe_instance,
+                                keyframe_instance,
                             );
 
                             // Add keyframe to nextEvents:
@@ -714,8 +1233,8 @@ pub fn resolve_all_states(
                             });
 
                             if let Some(end) = instance.end {
-                                let tmp_instance = parent_obj_instance.instance.try_lock().unwrap(); // TODO - eww
-                                if tmp_instance
+                                if parent_obj_instance
+                                    .instance
                                     .end
                                     .as_ref()
                                     .map(|p_end| end < *p_end)
@@ -729,8 +1248,6 @@ pub fn resolve_all_states(
                                     })
                                 }
                             }
-                        } else {
-                            unhandled = false;
                         }
                     }
                 }
@@ -742,6 +1259,23 @@ pub fn resolve_all_states(
         }
     }
 
+    for event in add_transition_ends(&mut layer_changes, &resolved_states.objects) {
+        event_object_times.insert(event.time);
+        resolved_states.next_events.push(event);
+    }
+
+    let (state, slots) = build_all_states(layer_changes, &resolved_states.objects, layer_slots);
+    resolved_states.state = state;
+    resolved_states.slots = slots;
+    resolved_states.aspiring = build_aspiring(aspired_instances);
+    for (layer, conflict) in open_conflicts {
+        resolved_states
+            .conflicts
+            .entry(layer)
+            .or_default()
+            .push(conflict);
+    }
+
     // Go through the keyframe events and add them to nextEvents:
     for event in keyframe_events {
         if event_object_times.insert(event.time) {
@@ -785,6 +1319,143 @@ struct TimeEvent {
     enable: bool,
 }
 
+/** Determine which of the instances aspiring to a layer takes precedence. The first one is the one on the layer */
+pub(crate) fn compare_aspiring_instances(
+    a: &ResolvedTimelineObjectInstance,
+    b: &ResolvedTimelineObjectInstance,
+    time: Time,
+) -> Ordering {
+    // First, sort using the priority at the time
+    let a_priority = a.priority_at(time);
+    let b_priority = b.priority_at(time);
+    if a_priority < b_priority {
+        return Ordering::Greater;
+    }
+    if a_priority > b_priority {
+        return Ordering::Less;
+    }
+
+    let a_start = a.instance.start;
+    let b_start = b.instance.start;
+
+    // Then, sort using the start time
+    if a_start < b_start {
+        return Ordering::Greater;
+    }
+    if a_start > b_start {
+        return Ordering::Less;
+    }
+
+    // Last resort: sort using id:
+    a.info.id.cmp(&b.info.id)
+}
+
+fn conflict_reason(
+    winner: &ResolvedTimelineObjectInstance,
+    loser: &ResolvedTimelineObjectInstance,
+    time: Time,
+) -> ConflictReason {
+    if winner.priority_at(time) != loser.priority_at(time) {
+        ConflictReason::Priority
+    } else if winner.instance.start != loser.instance.start {
+        ConflictReason::LaterStart
+    } else {
+        ConflictReason::IdTieBreak
+    }
+}
+
+fn update_layer_conflict(
+    conflicts: &mut HashMap<String, Vec<LayerConflict>>,
+    open_conflicts: &mut HashMap<String, LayerConflict>,
+    layer: &str,
+    time: Time,
+    aspiring_instances: &[Rc<ResolvedTimelineObjectInstance>],
+    objs_on_layer: &[Rc<ResolvedTimelineObjectInstance>],
+) {
+    let is_on_layer =
+        |obj: &Rc<ResolvedTimelineObjectInstance>| objs_on_layer.iter().any(|o| Rc::ptr_eq(o, obj));
+
+    // The aspiring instances are sorted by precedence, so the last winner is the one the losers had to beat
+    let losers = match aspiring_instances.iter().rfind(|obj| is_on_layer(obj)) {
+        Some(last_winner) => aspiring_instances
+            .iter()
+            .filter(|obj| !is_on_layer(obj))
+            .map(|obj| ShadowedObject {
+                object_id: obj.info.id.clone(),
+                reason: conflict_reason(last_winner, obj, time),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    let winners = objs_on_layer
+        .iter()
+        .map(|obj| obj.info.id.clone())
+        .collect::<Vec<_>>();
+
+    let unchanged = open_conflicts
+        .get(layer)
+        .is_some_and(|conflict| conflict.winners == winners && conflict.losers == losers);
+    if unchanged {
+        return;
+    }
+
+    if let Some(mut conflict) = open_conflicts.remove(layer) {
+        // Leave out conflicts that were resolved at the same time they started
+        if conflict.start < time {
+            conflict.end = Some(time);
+            conflicts
+                .entry(layer.to_string())
+                .or_default()
+                .push(conflict);
+        }
+    }
+    if !losers.is_empty() {
+        open_conflicts.insert(
+            layer.to_string(),
+            LayerConflict {
+                start: time,
+                end: None,
+                winners,
+                losers,
+            },
+        );
+    }
+}
+
+fn build_aspiring(
+    aspired_instances: HashMap<(String, String), (Rc<ResolvedTimelineObjectInstance>, bool)>,
+) -> HashMap<String, Vec<LookaheadObject>> {
+    let mut per_layer: HashMap<String, Vec<(Rc<ResolvedTimelineObjectInstance>, bool)>> =
+        HashMap::new();
+    for (_, aspired) in aspired_instances {
+        per_layer
+            .entry(aspired.0.info.layer.clone())
+            .or_default()
+            .push(aspired);
+    }
+
+    per_layer
+        .into_iter()
+        .map(|(layer, mut aspired)| {
+            aspired.sort_by(|(a, _), (b, _)| {
+                a.instance
+                    .start
+                    .cmp(&b.instance.start)
+                    .then_with(|| compare_aspiring_instances(a, b, a.instance.start))
+            });
+            let objects = aspired
+                .into_iter()
+                .map(|(obj, plays)| LookaheadObject {
+                    object_id: obj.info.id.clone(),
+                    instance: obj.instance.clone(),
+                    plays,
+                })
+                .collect();
+            (layer, objects)
+        })
+        .collect()
+}
+
 fn get_times_from_parent(
     resolved: &ResolvedTimeline,
     obj: &ResolvedTimelineObject,
@@ -819,52 +1490,314 @@ fn make_resolved_obj(
 ) -> Rc<ResolvedTimelineObjectInstance> {
     Rc::new(ResolvedTimelineObjectInstance {
         info: obj.info.clone(),
-        instance_id: instance.id.clone(),
-        instance: Rc::new(Mutex::new(instance.clone())),
+        instance: instance.clone(),
+        priority_segments: obj.resolved.priority_segments.clone(),
     })
 }
 
+/** A change of a layer, tracked while sweeping through the points in time. The instance is looked up once it has its final end time */
+#[derive(Clone)]
+struct LayerChange {
+    object_id: String,
+    instance_id: String,
+    keyframes: Vec<ResolvedTimelineObjectInstanceKeyframe>,
+    transition: Option<Box<LayerChangeTransition>>,
+}
+
+#[derive(Clone)]
+struct LayerChangeTransition {
+    outgoing: LayerChange,
+    start: Time,
+    end: Time,
+}
+
+impl LayerChange {
+    /** The change continued at a later time, without the keyframes and transition that have ended */
+    fn continued_at(&self, time: Time) -> LayerChange {
+        LayerChange {
+            object_id: self.object_id.clone(),
+            instance_id: self.instance_id.clone(),
+            keyframes: self
+                .keyframes
+                .iter()
+                .filter(|k| k.keyframe_end_time.unwrap_or(Time::MAX) > time)
+                .cloned()
+                .collect(),
+            transition: self
+                .transition
+                .clone()
+                .filter(|transition| transition.end > time),
+        }
+    }
+}
+
+/** The objects on each layer at the times they change, in the order of the layer. Empty means the layer becomes empty */
+type LayerChanges = HashMap<String, BTreeMap<Time, Vec<LayerChange>>>;
+
 fn set_state_at_time(
-    states: &mut AllStates,
+    changes: &mut LayerChanges,
     layer: &str,
     time: Time,
-    instance: Option<&Rc<ResolvedTimelineObjectInstance>>,
+    instances: &[Rc<ResolvedTimelineObjectInstance>],
 ) {
-    let layer_states = states.entry(layer.to_string()).or_default();
-    if let Some(instance) = instance {
-        layer_states.insert(
-            time,
-            Some(TimelineLayerState {
+    let layer_changes = changes.entry(layer.to_string()).or_default();
+
+    // Compare with the layer before this time, as it may already have been changed several times at this time
+    let previous = layer_changes
+        .range(..time)
+        .next_back()
+        .map(|(_, change)| change.clone())
+        .unwrap_or_default();
+    let is_same = |change: &LayerChange, instance: &ResolvedTimelineObjectInstance| {
+        change.object_id == instance.info.id && change.instance_id == instance.instance.id
+    };
+    let mut outgoing = previous
+        .iter()
+        .filter(|p| !instances.iter().any(|instance| is_same(p, instance)));
+
+    let change = instances
+        .iter()
+        .map(|instance| {
+            if let Some(previous) = previous.iter().find(|p| is_same(p, instance)) {
+                // Objects that were already on the layer keep their keyframes
+                return previous.continued_at(time);
+            }
+
+            // New objects replace the ones that are no longer on the layer, in order
+            let replaced = outgoing.next();
+            let transition = match (replaced, instance.info.transition_duration) {
+                (Some(replaced), Some(duration)) if duration > 0 => {
+                    let mut replaced = replaced.continued_at(time);
+                    replaced.transition = None;
+                    Some(Box::new(LayerChangeTransition {
+                        outgoing: replaced,
+                        start: time,
+                        end: time + duration,
+                    }))
+                }
+                _ => None,
+            };
+            LayerChange {
                 object_id: instance.info.id.clone(),
-                instance_id: Some(instance.instance_id.clone()),
-                instance: Some(instance.instance.clone()),
+                instance_id: instance.instance.id.clone(),
                 keyframes: Vec::new(),
-            }),
-        );
-    } else {
-        // TODO - this is messy, but it works
-        layer_states.insert(time, None);
-    }
+                transition,
+            }
+        })
+        .collect();
+    layer_changes.insert(time, change);
 }
 
 fn add_keyframe_at_time(
-    states: &mut AllStates,
+    changes: &mut LayerChanges,
     layer: &str,
     time: Time,
-    instance: &Rc<ResolvedTimelineObjectInstanceKeyframe>,
+    keyframe: ResolvedTimelineObjectInstanceKeyframe,
 ) {
-    let layer_states = states.entry(layer.to_string()).or_default();
-
-    let time_state = layer_states.entry(time).or_insert_with(|| {
-        Some(TimelineLayerState {
-            object_id: instance.info.id.clone(),
-            instance_id: None,
-            instance: None,
-            keyframes: Vec::new(),
+    let layer_changes = changes.entry(layer.to_string()).or_default();
+
+    if !layer_changes.contains_key(&time) {
+        // Continue from the previous change, without the keyframes that have ended
+        let previous = layer_changes
+            .range(..time)
+            .next_back()
+            .map(|(_, change)| change.clone())
+            .unwrap_or_default();
+        if !previous.is_empty() {
+            let change = previous
+                .iter()
+                .map(|previous| previous.continued_at(time))
+                .collect();
+            layer_changes.insert(time, change);
+        }
+    }
+
+    if let Some(change) = layer_changes.get_mut(&time).and_then(|change| {
+        change
+            .iter_mut()
+            .find(|change| keyframe.info.parent_id.as_ref() == Some(&change.object_id))
+    }) {
+        // Keep the keyframes in the order they are to be applied
+        let index = change
+            .keyframes
+            .partition_point(|existing| existing.info.priority <= keyframe.info.priority);
+        change.keyframes.insert(index, keyframe);
+    }
+}
+
+/** Continue the layers at the end of their transitions, so the end of a transition is a change of its own.
+ * Returns the events for the transitions that end
+ */
+fn add_transition_ends(
+    changes: &mut LayerChanges,
+    objects: &HashMap<String, ResolvedStatesForObject>,
+) -> Vec<NextEvent> {
+    let mut events = Vec::new();
+
+    for layer_changes in changes.values_mut() {
+        // A transition can't outlast the object it transitions from, now that its final end time is known
+        for (time, change) in layer_changes.iter_mut() {
+            for change in change.iter_mut() {
+                if let Some(transition) = &mut change.transition {
+                    let outgoing_end = objects
+                        .get(&transition.outgoing.object_id)
+                        .and_then(|obj| obj.instances.get(&transition.outgoing.instance_id))
+                        .and_then(|instance| instance.original_end);
+                    if let Some(outgoing_end) = outgoing_end {
+                        transition.end = transition.end.min(outgoing_end);
+                    }
+                    if transition.end <= *time {
+                        change.transition = None;
+                    }
+                }
+            }
+        }
+
+        let mut next_time = layer_changes.keys().next().copied();
+        while let Some(time) = next_time {
+            next_time = layer_changes
+                .range((Bound::Excluded(time), Bound::Unbounded))
+                .next()
+                .map(|(time, _)| *time);
+
+            let change = &layer_changes[&time];
+            let transition_end = change
+                .iter()
+                .filter_map(|change| change.transition.as_ref().map(|t| t.end))
+                .min();
+            if let Some(transition_end) = transition_end {
+                if next_time.is_none_or(|next_time| next_time > transition_end) {
+                    // Nothing else changes on the layer before the transition ends
+                    let continued = change
+                        .iter()
+                        .map(|change| change.continued_at(transition_end))
+                        .collect();
+                    layer_changes.insert(transition_end, continued);
+                    next_time = Some(transition_end);
+                }
+
+                // The transition only ends if the object is still on the layer by then
+                let change = &layer_changes[&time];
+                let ended = &layer_changes[&transition_end];
+                for change in change.iter().filter(|change| {
+                    change.transition.as_ref().map(|t| t.end) == Some(transition_end)
+                        && ended.iter().any(|ended| {
+                            ended.object_id == change.object_id
+                                && ended.instance_id == change.instance_id
+                        })
+                }) {
+                    events.push(NextEvent {
+                        event_type: EventType::TransitionEnd,
+                        time: transition_end,
+                        object_id: change.object_id.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    events
+}
+
+fn build_all_states(
+    changes: LayerChanges,
+    objects: &HashMap<String, ResolvedStatesForObject>,
+    layer_slots: &HashMap<String, LayerSlots>,
+) -> (AllStates, AllSlotStates) {
+    let mut state = HashMap::new();
+    let mut slots = HashMap::new();
+
+    for (layer, layer_changes) in changes {
+        let layer_states = layer_changes.into_iter().map(|(time, change)| {
+            let states = change
+                .into_iter()
+                .filter_map(|change| build_layer_state(change, time, objects))
+                .collect::<Vec<_>>();
+            (time, states)
+        });
+
+        if layer_slots.contains_key(&layer) {
+            slots.insert(layer, layer_states.collect());
+        } else {
+            let layer_states = layer_states
+                .map(|(time, states)| (time, states.into_iter().next()))
+                .collect();
+            state.insert(layer, layer_states);
+        }
+    }
+
+    (state, slots)
+}
+
+fn build_layer_state(
+    change: LayerChange,
+    time: Time,
+    objects: &HashMap<String, ResolvedStatesForObject>,
+) -> Option<TimelineLayerState> {
+    let obj = objects.get(&change.object_id)?;
+    let instance = obj.instances.get(&change.instance_id)?;
+
+    // Made from the same instance as one that was on the layer earlier
+    let resumed = instance.from_instance_id.is_some()
+        && obj.instances.values().any(|other| {
+            other.from_instance_id == instance.from_instance_id && other.start < instance.start
+        });
+
+    let transition = change.transition.and_then(|transition| {
+        let LayerChangeTransition {
+            outgoing,
+            start,
+            end,
+        } = *transition;
+        let mut outgoing = build_layer_state(outgoing, time, objects)?;
+        // The outgoing object stays until the transition ends
+        outgoing.instance.end = Some(end);
+        outgoing.set_time(time);
+        Some(LayerTransition {
+            outgoing: Box::new(outgoing),
+            start,
+            end,
+            progress: 0.0,
         })
     });
+    let mut state = TimelineLayerState {
+        object_id: change.object_id,
+        instance: instance.clone(),
+        keyframes: change.keyframes,
+        transition,
+        content_offset: 0,
+        remaining: None,
+        resumed,
+        pauses: obj.info.pauses.clone(),
+        paused: false,
+        playback_rate: obj.info.parent_playback_rate,
+    };
+    state.set_time(time);
+    Some(state)
+}
 
-    if let Some(time_state) = time_state {
-        time_state.keyframes.push(instance.clone());
+/** The objects that get to be on a layer, from the aspiring ones sorted by precedence */
+fn select_layer_occupants(
+    aspiring_instances: &[Rc<ResolvedTimelineObjectInstance>],
+    slots: Option<&LayerSlots>,
+) -> Vec<Rc<ResolvedTimelineObjectInstance>> {
+    if let Some(slots) = slots {
+        let mut occupants = aspiring_instances
+            .iter()
+            .take(slots.capacity.unwrap_or(usize::MAX))
+            .cloned()
+            .collect::<Vec<_>>();
+        if slots.order == SlotOrder::Start {
+            occupants.sort_by(|a, b| {
+                a.instance
+                    .start
+                    .cmp(&b.instance.start)
+                    .then_with(|| a.info.id.cmp(&b.info.id))
+            });
+        }
+        occupants
+    } else {
+        // Only the one on top
+        aspiring_instances.iter().take(1).cloned().collect()
     }
 }
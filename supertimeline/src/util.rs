@@ -7,14 +7,15 @@ use crate::lookup_expression::LookupExpressionResultType;
 use crate::references::ReferencesBuilder;
 use crate::resolver::ResolverContext;
 use crate::resolver::TimeWithReference;
+use std::borrow::Borrow;
 use std::cmp::{max, min};
 use std::collections::HashSet;
 
 pub type Time = u64;
 
-pub fn invert_instances(
+pub fn invert_instances<T: Borrow<TimelineObjectInstance>>(
     ctx: &ResolverContext,
-    instances: &[TimelineObjectInstance],
+    instances: &[T],
 ) -> Vec<TimelineObjectInstance> {
     if instances.is_empty() {
         vec![TimelineObjectInstance {
@@ -88,16 +89,16 @@ pub fn invert_instances(
 }
 
 // Cleanup instances. Join overlaps or touching etc
-pub fn clean_instances(
+pub fn clean_instances<T: Borrow<TimelineObjectInstance>>(
     ctx: &ResolverContext,
-    instances: &[TimelineObjectInstance],
+    instances: &[T],
     allow_merge: bool,
     allow_zero_gaps: bool,
 ) -> Vec<TimelineObjectInstance> {
     match instances.len() {
         0 => Vec::new(),
         1 => {
-            let mut instance = instances[0].clone();
+            let mut instance = instances[0].borrow().clone();
             instance.original_start = Some(instance.start);
             instance.original_end = instance.end;
 
@@ -107,6 +108,7 @@ pub fn clean_instances(
             let mut events = Vec::new();
 
             for instance in instances {
+                let instance = instance.borrow();
                 events.push(EventForInstance {
                     time: instance.start,
                     is_start: true,
@@ -325,10 +327,90 @@ where
     }
 }
 
+/** Limits on the repeating of a single enable, applied on top of the limits in ResolveOptions */
+#[derive(Debug, Default)]
+pub struct RepeatLimits {
+    /** The maximum number of repeats, counted from the first */
+    pub count: Option<usize>,
+    /** Points in time where the repeating should stop */
+    pub until: Vec<TimeWithReference>,
+}
+
+impl RepeatLimits {
+    /** The first stop point after the instance started is the one that applies */
+    pub fn until_for(&self, instance: &TimelineObjectInstance) -> Option<&TimeWithReference> {
+        self.until
+            .iter()
+            .filter(|until| until.value > instance.start)
+            .min_by_key(|until| until.value)
+    }
+}
+
+/** The cap of the parent instance that a repeating instance is tied to */
+pub fn find_repeat_cap(instance: &TimelineObjectInstance) -> Option<&Cap> {
+    instance
+        .caps
+        .iter()
+        .find(|cap| instance.references.contains(&cap.id))
+}
+
+/** Create a single repeat of an instance, capped inside of its parent and any repeat-until */
+pub fn create_repeated_instance(
+    ctx: &ResolverContext,
+    instance: &TimelineObjectInstance,
+    cap: Option<&Cap>,
+    until: Option<&TimeWithReference>,
+    repeat_references: &HashSet<String>,
+    start_time: Time,
+    end_time: Option<Time>,
+) -> Option<TimelineObjectInstance> {
+    let capped_start_time = cap
+        .map(|cap| max(cap.start, start_time))
+        .unwrap_or(start_time);
+    let capped_end_time = if let Some(end_time) = end_time {
+        Some(
+            cap.and_then(|cap| cap.end)
+                .map(|cap_end| min(cap_end, end_time))
+                .unwrap_or(end_time),
+        )
+    } else {
+        None
+    };
+    let (capped_end_time, capped_by_until) = match (capped_end_time, until) {
+        (Some(end), Some(until)) if until.value < end => (Some(until.value), true),
+        (None, Some(until)) => (Some(until.value), true),
+        (end, _) => (end, false),
+    };
+
+    if capped_end_time.unwrap_or(Time::MAX) > capped_start_time {
+        let references = ReferencesBuilder::new()
+            .add_id(&instance.id)
+            .add(&instance.references)
+            .add(repeat_references)
+            .add_some(until.filter(|_| capped_by_until).map(|u| &u.references))
+            .done();
+        Some(TimelineObjectInstance {
+            id: ctx.generate_id(),
+            start: capped_start_time,
+            end: capped_end_time,
+            references,
+
+            is_first: false,
+            original_start: None,
+            original_end: None,
+            caps: Vec::new(),
+            from_instance_id: None,
+        })
+    } else {
+        None
+    }
+}
+
 pub fn apply_repeating_instances(
     ctx: &ResolverContext,
     instances: Vec<TimelineObjectInstance>,
     repeat_time: Option<TimeWithReference>,
+    repeat_limits: &RepeatLimits,
     options: &ResolveOptions,
 ) -> Vec<TimelineObjectInstance> {
     if let Some(repeat_time) = &repeat_time {
@@ -355,50 +437,45 @@ pub fn apply_repeating_instances(
                 );
                 let mut end_time = instance.end.map(|end| end + (start_time - instance.start));
 
-                let cap = instance
-                    .caps
-                    .iter()
-                    .find(|cap| instance.references.contains(&cap.id));
+                let cap = find_repeat_cap(instance);
+                let until = repeat_limits.until_for(instance);
 
                 let limit = options.limit_count.unwrap_or(DEFAULT_LIMIT_COUNT);
-                for _i in 0..limit {
-                    if let Some(limit_time) = options.limit_time {
-                        if start_time >= limit_time {
+                for i in 0.. {
+                    if let Some(count) = repeat_limits.count {
+                        let index = (start_time - instance.start) / repeat_time.value;
+                        if index >= count as u64 {
+                            break;
+                        }
+                    }
+                    if let Some(until) = until {
+                        if start_time >= until.value {
                             break;
                         }
                     }
+                    if cap
+                        .and_then(|cap| cap.end)
+                        .is_some_and(|end| start_time >= end)
+                    {
+                        // The parent has ended
+                        break;
+                    }
+                    if i >= limit || options.limit_time.is_some_and(|t| start_time >= t) {
+                        // There are more repeats than were resolved
+                        ctx.truncated_at(start_time);
+                        break;
+                    }
 
-                    let capped_start_time = cap
-                        .map(|cap| max(cap.start, start_time))
-                        .unwrap_or(start_time);
-                    let capped_end_time = if let Some(end_time) = end_time {
-                        Some(
-                            cap.and_then(|cap| cap.end)
-                                .map(|cap_end| min(cap_end, end_time))
-                                .unwrap_or(end_time),
-                        )
-                    } else {
-                        None
-                    };
-
-                    if capped_end_time.unwrap_or(Time::MAX) > capped_start_time {
-                        let references = ReferencesBuilder::new()
-                            .add_id(&instance.id)
-                            .add(&instance.references)
-                            .add(&repeat_time.references)
-                            .done();
-                        repeated_instances.push(TimelineObjectInstance {
-                            id: ctx.generate_id(),
-                            start: capped_start_time,
-                            end: capped_end_time,
-                            references,
-
-                            is_first: false,
-                            original_start: None,
-                            original_end: None,
-                            caps: Vec::new(),
-                            from_instance_id: None,
-                        })
+                    if let Some(repeated_instance) = create_repeated_instance(
+                        ctx,
+                        instance,
+                        cap,
+                        until,
+                        &repeat_time.references,
+                        start_time,
+                        end_time,
+                    ) {
+                        repeated_instances.push(repeated_instance);
                     }
 
                     start_time += repeat_time.value;
@@ -502,6 +579,23 @@ pub fn cap_instance(
     }
 }
 
+/** Like cap_instance, but only the start is capped. The instance has to start while the parent plays, but may end after it */
+pub fn cap_instance_start(
+    instance: &TimelineObjectInstance,
+    parent: &TimelineObjectInstance,
+) -> Option<TimelineObjectInstance> {
+    let parent_end = parent.end.unwrap_or(Time::MAX);
+    if instance.start >= parent_end || instance.end.unwrap_or(Time::MAX) <= parent.start {
+        return None;
+    }
+
+    let mut instance2 = instance.clone();
+    if instance.start < parent.start {
+        set_instance_start_time(&mut instance2, parent.start)
+    }
+    Some(instance2)
+}
+
 pub fn set_instance_end_time(instance: &mut TimelineObjectInstance, end: Time) {
     if instance.original_end.is_none() {
         instance.original_end = instance.end;
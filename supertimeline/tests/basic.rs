@@ -2,18 +2,24 @@ extern crate supertimeline;
 mod objs;
 mod util;
 
+use crate::objs::layer_obj;
 use crate::objs::SimpleKeyframe;
 use crate::objs::SimpleTimelineObj;
-use crate::util::assert_instances;
 use crate::util::assert_obj_on_layer;
-use std::collections::HashSet;
+use crate::util::{assert_instances, assert_instances2, instance_times};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use supertimeline::get_state;
-use supertimeline::TimelineObjectInstance;
 use supertimeline::{
-    resolve_all_states, resolve_timeline, EventType, Expression, NextEvent, ResolveOptions,
-    TimelineEnable,
+    diff_resolved_timelines, diff_states, extend_resolved_timeline, resolve_all_states,
+    resolve_all_states_with_slots, resolve_timeline, ConflictReason, EventType, Expression,
+    InstanceDiff, LayerItem, LayerSlots, LayerStateDiff, LookaheadObject, NextEvent,
+    ResolveOptions, ResolvedPrioritySegment, ResolvedStates,
+    ResolvedTimelineObjectInstanceKeyframe, ShadowedObject, SlotOrder, StateChange, TimeRange,
+    TimelineEnable, TimelineLayerState, TimelinePause, TimelinePrioritySegment, TimelineRecurrence,
+    TimelineState,
 };
+use supertimeline::{Time, TimelineObjectInstance};
 
 #[test]
 fn simple_timeline() {
@@ -609,8 +615,7 @@ fn unique_instance_ids() {
 
     for obj in states.objects {
         for (id, instance) in obj.1.instances {
-            let locked = instance.lock().unwrap();
-            assert_eq!(locked.id, id);
+            assert_eq!(instance.id, id);
             instance_ids.insert(id);
             instance_count += 1;
         }
@@ -1007,3 +1012,1659 @@ fn continuous_combined_negated_and_normal_classes_on_different_objects() {
         assert_eq!(layer.keyframes.len(), 1);
     }
 }
+
+#[test]
+fn repeat_count() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        (SimpleTimelineObj {
+            id: "video0".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                enable_end: Some(Expression::Number(5)),
+                repeating: Some(Expression::Number(10)),
+                repeat_count: Some(3),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+    ];
+
+    let resolve_at = |time, limit_count| {
+        let options = ResolveOptions {
+            time,
+            limit_count: Some(limit_count),
+            limit_time: None,
+        };
+        let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+        let obj_video0 = resolved
+            .objects
+            .get("video0")
+            .expect("Missing video0 object");
+        obj_video0.resolved.instances.clone()
+    };
+
+    assert_instances2(
+        &resolve_at(0, 99),
+        &vec![
+            Rc::new(TimelineObjectInstance {
+                start: 0,
+                end: Some(5),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 10,
+                end: Some(15),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 20,
+                end: Some(25),
+                ..Default::default()
+            }),
+        ],
+    );
+
+    // The global limit still applies
+    assert_instances2(
+        &resolve_at(0, 2),
+        &vec![
+            Rc::new(TimelineObjectInstance {
+                start: 0,
+                end: Some(5),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 10,
+                end: Some(15),
+                ..Default::default()
+            }),
+        ],
+    );
+
+    // Repeats are counted from the first, not from the resolve time
+    assert_instances2(
+        &resolve_at(12, 99),
+        &vec![
+            Rc::new(TimelineObjectInstance {
+                start: 10,
+                end: Some(15),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 20,
+                end: Some(25),
+                ..Default::default()
+            }),
+        ],
+    );
+    assert_instances2(&resolve_at(30, 99), &vec![]);
+}
+
+#[test]
+fn repeat_until() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        (SimpleTimelineObj {
+            id: "show".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                enable_end: Some(Expression::Number(33)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        (SimpleTimelineObj {
+            id: "sting".to_string(),
+            layer: "1".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(5)),
+                repeating: Some(Expression::Number(10)),
+                repeat_until: Some(Expression::String("#show.end".to_string())),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+    ];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: Some(99),
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let obj_sting = resolved.objects.get("sting").expect("Missing sting object");
+    assert!(obj_sting.resolved.direct_references.contains("#show"));
+    assert_instances2(
+        &obj_sting.resolved.instances,
+        &vec![
+            Rc::new(TimelineObjectInstance {
+                start: 0,
+                end: Some(5),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 10,
+                end: Some(15),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 20,
+                end: Some(25),
+                ..Default::default()
+            }),
+            // Ended by the show
+            Rc::new(TimelineObjectInstance {
+                start: 30,
+                end: Some(33),
+                ..Default::default()
+            }),
+        ],
+    );
+
+    let state0 = get_state(&states, 31, None);
+    assert_obj_on_layer(&state0, "1", "sting");
+    let state1 = get_state(&states, 40, None);
+    assert!(!state1.layers.contains_key("1"));
+}
+
+fn recurring_obj(start: u64, recurrence: TimelineRecurrence) -> Vec<SimpleTimelineObj> {
+    vec![SimpleTimelineObj {
+        id: "news".to_string(),
+        layer: "0".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(start as i64)),
+            duration: Some(Expression::Number(3_600_000)),
+            recurrence: Some(recurrence),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }]
+}
+
+#[cfg(feature = "recurrence")]
+fn hour_long_instances(starts: &[u64]) -> Vec<Rc<TimelineObjectInstance>> {
+    starts
+        .iter()
+        .map(|start| {
+            Rc::new(TimelineObjectInstance {
+                start: *start,
+                end: Some(*start + 3_600_000),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "recurrence")]
+#[test]
+fn recurrence_weekdays() {
+    // Monday 2024-01-01 00:00 UTC
+    let timeline = recurring_obj(
+        1704067200000,
+        TimelineRecurrence {
+            rule: "FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR;BYHOUR=6;BYMINUTE=0".to_string(),
+            timezone: Some("Europe/London".to_string()),
+        },
+    );
+
+    let options = ResolveOptions {
+        time: 1704067200000,
+        limit_count: Some(7),
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let day = 86_400_000;
+    let first = 1704088800000; // Monday 06:00
+    let obj = resolved.objects.get("news").expect("Missing news object");
+    assert_instances2(
+        &obj.resolved.instances,
+        &hour_long_instances(&[
+            first,
+            first + day,
+            first + 2 * day,
+            first + 3 * day,
+            first + 4 * day,
+            // Skips the weekend
+            first + 7 * day,
+            first + 8 * day,
+        ]),
+    );
+}
+
+#[cfg(feature = "recurrence")]
+#[test]
+fn recurrence_daylight_saving() {
+    // Friday 2024-03-29, the clocks in London go forward on sunday
+    let timeline = recurring_obj(
+        1711670400000,
+        TimelineRecurrence {
+            rule: "FREQ=DAILY;COUNT=4;BYHOUR=6".to_string(),
+            timezone: Some("Europe/London".to_string()),
+        },
+    );
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: Some(99),
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let obj = resolved.objects.get("news").expect("Missing news object");
+    assert_instances2(
+        &obj.resolved.instances,
+        &hour_long_instances(&[
+            1711692000000, // 06:00 GMT
+            1711778400000,
+            1711861200000, // 06:00 BST
+            1711947600000,
+        ]),
+    );
+}
+
+#[cfg(feature = "recurrence")]
+#[test]
+fn recurrence_first_monday_of_month() {
+    let timeline = recurring_obj(
+        1704067200000,
+        TimelineRecurrence {
+            rule: "FREQ=MONTHLY;BYDAY=1MO".to_string(),
+            timezone: None,
+        },
+    );
+
+    // Resolve from 2024-02-10, which starts with the last occurrence before it
+    let options = ResolveOptions {
+        time: 1707523200000,
+        limit_count: Some(3),
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let obj = resolved.objects.get("news").expect("Missing news object");
+    assert_instances2(
+        &obj.resolved.instances,
+        &hour_long_instances(&[1707091200000, 1709510400000, 1711929600000]),
+    );
+}
+
+#[cfg(feature = "recurrence")]
+#[test]
+fn recurrence_past_supported_dates() {
+    // The second occurrence is further away than any date can be
+    let timeline = recurring_obj(
+        0,
+        TimelineRecurrence {
+            rule: "FREQ=DAILY;INTERVAL=100000000".to_string(),
+            timezone: None,
+        },
+    );
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let obj = resolved.objects.get("news").expect("Missing news object");
+    assert_instances2(&obj.resolved.instances, &hour_long_instances(&[0]));
+}
+
+#[test]
+fn recurrence_bad_rule() {
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+
+    let timeline = recurring_obj(
+        0,
+        TimelineRecurrence {
+            rule: "FREQ=HOURLY".to_string(),
+            timezone: None,
+        },
+    );
+    assert!(resolve_timeline(&timeline, options.clone()).is_err());
+
+    let timeline = recurring_obj(
+        0,
+        TimelineRecurrence {
+            rule: "FREQ=DAILY".to_string(),
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+        },
+    );
+    assert!(resolve_timeline(&timeline, options.clone()).is_err());
+
+    // Parts that the frequency doesn't allow
+    for rule in &[
+        "FREQ=DAILY;BYDAY=1MO",
+        "FREQ=WEEKLY;BYDAY=1MO",
+        "FREQ=WEEKLY;BYMONTHDAY=5",
+    ] {
+        let timeline = recurring_obj(
+            0,
+            TimelineRecurrence {
+                rule: rule.to_string(),
+                timezone: None,
+            },
+        );
+        assert!(
+            resolve_timeline(&timeline, options.clone()).is_err(),
+            "Expected '{}' to be rejected",
+            rule
+        );
+    }
+}
+
+#[test]
+fn limit_count_override() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        (SimpleTimelineObj {
+            id: "obj0".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(5)),
+                repeating: Some(Expression::Number(10)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        (SimpleTimelineObj {
+            id: "obj1".to_string(),
+            layer: "1".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(5)),
+                repeating: Some(Expression::Number(10)),
+                ..Default::default()
+            }],
+            limit_count: Some(4),
+            ..Default::default()
+        }),
+        (SimpleTimelineObj {
+            id: "obj2".to_string(),
+            layer: "2".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(5)),
+                repeating: Some(Expression::Number(10)),
+                ..Default::default()
+            }],
+            limit_count: Some(99),
+            limit_time: Some(30),
+            ..Default::default()
+        }),
+    ];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let obj0 = resolved.objects.get("obj0").expect("Missing obj0 object");
+    let obj1 = resolved.objects.get("obj1").expect("Missing obj1 object");
+    let obj2 = resolved.objects.get("obj2").expect("Missing obj2 object");
+
+    let instances = |starts: &[Time]| -> Vec<Rc<TimelineObjectInstance>> {
+        starts
+            .iter()
+            .map(|start| {
+                Rc::new(TimelineObjectInstance {
+                    start: *start,
+                    end: Some(start + 5),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    };
+
+    // Uses the default limit
+    assert_instances2(&obj0.resolved.instances, &instances(&[0, 10]));
+    assert_instances2(&obj1.resolved.instances, &instances(&[0, 10, 20, 30]));
+    assert_instances2(&obj2.resolved.instances, &instances(&[0, 10, 20]));
+}
+
+#[test]
+fn valid_until() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        (SimpleTimelineObj {
+            id: "obj0".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(5)),
+                repeating: Some(Expression::Number(10)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        (SimpleTimelineObj {
+            id: "obj1".to_string(),
+            layer: "1".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(3)),
+                repeating: Some(Expression::Number(7)),
+                repeat_count: Some(2), // Ends before the limit
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+    ];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    assert_eq!(resolved.valid_until, Some(20));
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    assert_eq!(states.valid_until, Some(20));
+
+    let extended = extend_resolved_timeline(&timeline, &resolved)
+        .expect("Extend timeline failed")
+        .expect("Timeline should be extended");
+    assert_eq!(extended.options.time, 0);
+    assert_eq!(extended.valid_until, Some(40));
+
+    // The instances before valid_until are kept
+    let obj0 = extended.objects.get("obj0").expect("Missing obj0 object");
+    assert_instances2(
+        &obj0.resolved.instances,
+        &vec![
+            Rc::new(TimelineObjectInstance {
+                start: 0,
+                end: Some(5),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 10,
+                end: Some(15),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 20,
+                end: Some(25),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 30,
+                end: Some(35),
+                ..Default::default()
+            }),
+        ],
+    );
+
+    let obj1 = extended.objects.get("obj1").expect("Missing obj1 object");
+    assert_eq!(obj1.resolved.instances.len(), 2);
+
+    let ids = extended
+        .objects
+        .values()
+        .flat_map(|obj| obj.resolved.instances.iter().map(|i| i.id.clone()))
+        .collect::<Vec<_>>();
+    let unique_ids = ids.iter().collect::<HashSet<_>>();
+    assert_eq!(ids.len(), unique_ids.len());
+
+    let states = resolve_all_states(&extended, None).expect("Resolve states failed");
+    assert_obj_on_layer(&get_state(&states, 12, None), "0", "obj0");
+    assert_obj_on_layer(&get_state(&states, 32, None), "0", "obj0");
+
+    // Nothing is cut off when the repeating ends by itself
+    let complete = resolve_timeline(
+        &timeline[1..],
+        ResolveOptions {
+            time: 0,
+            limit_count: None,
+            limit_time: None,
+        },
+    )
+    .expect("Resolve timeline failed");
+    assert_eq!(complete.valid_until, None);
+    assert!(extend_resolved_timeline(&timeline[1..], &complete)
+        .expect("Extend timeline failed")
+        .is_none());
+}
+
+#[test]
+fn keyframe_references_and_priority() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        (SimpleTimelineObj {
+            id: "video".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                enable_end: Some(Expression::Number(100)),
+                ..Default::default()
+            }],
+            keyframes: vec![
+                (SimpleKeyframe {
+                    id: "kf0".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(10)),
+                        ..Default::default()
+                    }],
+                    classes: vec!["fade".to_string()],
+                    priority: 1,
+                    ..Default::default()
+                }),
+                (SimpleKeyframe {
+                    id: "kf1".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(20)),
+                        enable_end: Some(Expression::Number(50)),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                (SimpleKeyframe {
+                    id: "kf2".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(30)),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }),
+        (SimpleTimelineObj {
+            id: "graphic".to_string(),
+            layer: "1".to_string(),
+            enable: vec![TimelineEnable {
+                enable_while: Some(Expression::String(".fade".to_string())),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        (SimpleTimelineObj {
+            id: "audio".to_string(),
+            layer: "2".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::String("#kf1.start + 5".to_string())),
+                duration: Some(Expression::Number(10)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+    ];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    assert_eq!(resolved.classes.get("fade"), Some(&vec!["kf0".to_string()]));
+    assert!(!resolved
+        .layers
+        .values()
+        .flatten()
+        .any(|id| id.starts_with("kf")));
+
+    let obj_graphic = resolved
+        .objects
+        .get("graphic")
+        .expect("Missing graphic object");
+    assert_instances2(
+        &obj_graphic.resolved.instances,
+        &vec![Rc::new(TimelineObjectInstance {
+            start: 10,
+            end: Some(100),
+            ..Default::default()
+        })],
+    );
+    let obj_audio = resolved.objects.get("audio").expect("Missing audio object");
+    assert_instances2(
+        &obj_audio.resolved.instances,
+        &vec![Rc::new(TimelineObjectInstance {
+            start: 25,
+            end: Some(35),
+            ..Default::default()
+        })],
+    );
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    let keyframe_ids = |time: Time| -> Vec<String> {
+        let state = get_state(&states, time, None);
+        let layer = state.layers.get("0").expect("Missing layer 0");
+        layer
+            .keyframes
+            .iter()
+            .map(|keyframe| keyframe.info.id.clone())
+            .collect()
+    };
+
+    assert_eq!(keyframe_ids(15), vec!["kf0"]);
+    // kf0 has the highest priority, so is applied last
+    assert_eq!(keyframe_ids(25), vec!["kf1", "kf0"]);
+    // Same priority, applied in the order they started
+    assert_eq!(keyframe_ids(35), vec!["kf1", "kf2", "kf0"]);
+    assert_eq!(keyframe_ids(60), vec!["kf2", "kf0"]);
+}
+
+#[test]
+fn keyframe_duration() {
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "video".to_string(),
+        layer: "0".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(0)),
+            enable_end: Some(Expression::Number(100)),
+            ..Default::default()
+        }],
+        keyframes: vec![
+            (SimpleKeyframe {
+                id: "kf0".to_string(),
+                enable: vec![TimelineEnable {
+                    enable_start: Some(Expression::Number(10)),
+                    ..Default::default()
+                }],
+                duration: Some(Expression::Number(5)),
+                ..Default::default()
+            }),
+            (SimpleKeyframe {
+                id: "kf1".to_string(),
+                enable: vec![TimelineEnable {
+                    enable_start: Some(Expression::Number(20)),
+                    enable_end: Some(Expression::Number(40)),
+                    ..Default::default()
+                }],
+                // The end of the enable is used instead
+                duration: Some(Expression::Number(5)),
+                ..Default::default()
+            }),
+        ],
+        ..Default::default()
+    }];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let kf0 = resolved.objects.get("kf0").expect("Missing kf0 object");
+    assert_instances2(
+        &kf0.resolved.instances,
+        &vec![Rc::new(TimelineObjectInstance {
+            start: 10,
+            end: Some(15),
+            ..Default::default()
+        })],
+    );
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    let keyframes = |time: Time| -> Vec<(String, Option<Time>)> {
+        let state = get_state(&states, time, None);
+        let layer = state.layers.get("0").expect("Missing layer 0");
+        layer
+            .keyframes
+            .iter()
+            .map(|keyframe| (keyframe.info.id.clone(), keyframe.keyframe_end_time))
+            .collect()
+    };
+
+    assert_eq!(keyframes(12), vec![("kf0".to_string(), Some(15))]);
+    assert_eq!(keyframes(16), vec![]);
+    assert_eq!(keyframes(30), vec![("kf1".to_string(), Some(40))]);
+    assert!(states
+        .next_events
+        .iter()
+        .any(|event| event.event_type == EventType::KeyFrame && event.time == 15));
+}
+
+#[test]
+fn states_are_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+
+    assert_send_sync::<ResolvedStates>();
+    assert_send_sync::<TimelineState>();
+    assert_send_sync::<TimelineLayerState>();
+
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "video".to_string(),
+        layer: "0".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(0)),
+            enable_end: Some(Expression::Number(100)),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let state = std::thread::spawn(move || get_state(&states, 50, None))
+        .join()
+        .expect("Thread failed");
+    assert_obj_on_layer(&state, "0", "video");
+}
+
+#[test]
+fn get_state_at_change_points() {
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "obj0".to_string(),
+        layer: "0".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(0)),
+            duration: Some(Expression::Number(5)),
+            repeating: Some(Expression::Number(10)),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: Some(10),
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let layer_times = states
+        .state
+        .get("0")
+        .expect("Missing layer 0")
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(layer_times.len(), 20);
+    assert!(layer_times.windows(2).all(|w| w[0] < w[1]));
+
+    assert_obj_on_layer(&get_state(&states, 0, None), "0", "obj0");
+    assert_obj_on_layer(&get_state(&states, 4, None), "0", "obj0");
+    assert!(!get_state(&states, 5, None).layers.contains_key("0"));
+    assert_obj_on_layer(&get_state(&states, 90, None), "0", "obj0");
+    assert!(!get_state(&states, 200, None).layers.contains_key("0"));
+
+    let state = get_state(&states, 10, Some(2));
+    assert_eq!(
+        state.next_events,
+        vec![
+            NextEvent {
+                event_type: EventType::End,
+                time: 15,
+                object_id: "obj0".to_string(),
+            },
+            NextEvent {
+                event_type: EventType::Start,
+                time: 20,
+                object_id: "obj0".to_string(),
+            },
+        ]
+    );
+    assert!(get_state(&states, 95, None).next_events.is_empty());
+}
+
+#[test]
+fn state_changes() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        SimpleTimelineObj {
+            id: "obj0".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                enable_end: Some(Expression::Number(50)),
+                ..Default::default()
+            }],
+            keyframes: vec![
+                (SimpleKeyframe {
+                    id: "kf0".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(10)),
+                        enable_end: Some(Expression::Number(20)),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        },
+        SimpleTimelineObj {
+            id: "obj1".to_string(),
+            layer: "0".to_string(),
+            priority: 1,
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(30)),
+                enable_end: Some(Expression::Number(40)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let summarize = |change: StateChange| {
+        let object_id = |state: &Option<TimelineLayerState>| {
+            state.as_ref().map(|state| state.object_id.clone())
+        };
+        let keyframe_ids = |keyframes: &Vec<ResolvedTimelineObjectInstanceKeyframe>| {
+            keyframes
+                .iter()
+                .map(|keyframe| keyframe.info.id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(change.layer, "0");
+        (
+            change.time,
+            object_id(&change.previous),
+            object_id(&change.current),
+            keyframe_ids(&change.keyframes_added),
+            keyframe_ids(&change.keyframes_removed),
+        )
+    };
+    let obj = |id: &str| Some(id.to_string());
+    let kfs = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(
+        states.changes(0, 100).map(summarize).collect::<Vec<_>>(),
+        vec![
+            (0, None, obj("obj0"), kfs(&[]), kfs(&[])),
+            (10, obj("obj0"), obj("obj0"), kfs(&["kf0"]), kfs(&[])),
+            (20, obj("obj0"), obj("obj0"), kfs(&[]), kfs(&["kf0"])),
+            (30, obj("obj0"), obj("obj1"), kfs(&[]), kfs(&[])),
+            (40, obj("obj1"), obj("obj0"), kfs(&[]), kfs(&[])),
+            (50, obj("obj0"), None, kfs(&[]), kfs(&[])),
+        ]
+    );
+
+    // Only the changes within the range, compared to the state just before them
+    assert_eq!(
+        states.changes(15, 35).map(summarize).collect::<Vec<_>>(),
+        vec![
+            (20, obj("obj0"), obj("obj0"), kfs(&[]), kfs(&["kf0"])),
+            (30, obj("obj0"), obj("obj1"), kfs(&[]), kfs(&[])),
+        ]
+    );
+    assert_eq!(states.changes(50, 50).count(), 0);
+
+    // The changes are found as they are iterated over
+    assert_eq!(
+        states
+            .changes(15, Time::MAX)
+            .take(1)
+            .map(summarize)
+            .collect::<Vec<_>>(),
+        vec![(20, obj("obj0"), obj("obj0"), kfs(&[]), kfs(&["kf0"]))]
+    );
+}
+
+#[test]
+fn diff_states_and_timelines() {
+    let make_timeline = |graphic_start: i64, keyframe: bool| -> Vec<SimpleTimelineObj> {
+        vec![
+            SimpleTimelineObj {
+                id: "video".to_string(),
+                layer: "0".to_string(),
+                enable: vec![TimelineEnable {
+                    enable_start: Some(Expression::Number(0)),
+                    enable_end: Some(Expression::Number(100)),
+                    ..Default::default()
+                }],
+                keyframes: if keyframe {
+                    vec![SimpleKeyframe {
+                        id: "kf0".to_string(),
+                        enable: vec![TimelineEnable {
+                            enable_start: Some(Expression::Number(0)),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }]
+                } else {
+                    Vec::new()
+                },
+                ..Default::default()
+            },
+            SimpleTimelineObj {
+                id: "graphic".to_string(),
+                layer: "1".to_string(),
+                enable: vec![TimelineEnable {
+                    enable_start: Some(Expression::Number(graphic_start)),
+                    duration: Some(Expression::Number(10)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        ]
+    };
+    let resolve = |timeline: &Vec<SimpleTimelineObj>| {
+        let options = ResolveOptions {
+            time: 0,
+            limit_count: None,
+            limit_time: None,
+        };
+        let resolved = resolve_timeline(timeline, options).expect("Resolve timeline failed");
+        let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+        (resolved, states)
+    };
+
+    let (resolved0, states0) = resolve(&make_timeline(10, false));
+    let (resolved1, states1) = resolve(&make_timeline(15, true));
+
+    // Resolving the same timeline again gives new instance ids, but nothing has changed
+    let (resolved0_again, states0_again) = resolve(&make_timeline(10, false));
+    assert!(diff_resolved_timelines(&resolved0, &resolved0_again).is_empty());
+    assert!(diff_states(
+        &get_state(&states0, 12, None),
+        &get_state(&states0_again, 12, None)
+    )
+    .is_empty());
+
+    let diff = diff_resolved_timelines(&resolved0, &resolved1);
+    assert_eq!(diff.objects.len(), 2);
+    match diff
+        .objects
+        .get("graphic")
+        .expect("Missing graphic")
+        .as_slice()
+    {
+        [InstanceDiff::Moved { previous, current }] => {
+            assert_eq!((previous.start, previous.end), (10, Some(20)));
+            assert_eq!((current.start, current.end), (15, Some(25)));
+        }
+        other => panic!("Unexpected diff {:?}", other),
+    }
+    match diff.objects.get("kf0").expect("Missing kf0").as_slice() {
+        [InstanceDiff::Added(instance)] => assert_eq!(instance.start, 0),
+        other => panic!("Unexpected diff {:?}", other),
+    }
+
+    let diff = diff_states(
+        &get_state(&states0, 12, None),
+        &get_state(&states1, 12, None),
+    );
+    assert_eq!(diff.layers.len(), 2);
+    match diff.layers.get("0").expect("Missing layer 0") {
+        LayerStateDiff::KeyframesChanged {
+            keyframes_added,
+            keyframes_removed,
+            ..
+        } => {
+            assert_eq!(keyframes_added, &vec!["kf0".to_string()]);
+            assert!(keyframes_removed.is_empty());
+        }
+        other => panic!("Unexpected diff {:?}", other),
+    }
+    match diff.layers.get("1").expect("Missing layer 1") {
+        LayerStateDiff::Removed(state) => assert_eq!(state.object_id, "graphic"),
+        other => panic!("Unexpected diff {:?}", other),
+    }
+
+    let diff = diff_states(
+        &get_state(&states0, 18, None),
+        &get_state(&states1, 18, None),
+    );
+    match diff.layers.get("1").expect("Missing layer 1") {
+        LayerStateDiff::Replaced { previous, current } => {
+            assert_eq!(previous.instance.start, 10);
+            assert_eq!(current.instance.start, 15);
+        }
+        other => panic!("Unexpected diff {:?}", other),
+    }
+}
+
+#[test]
+fn layer_lookahead() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        layer_obj("background", "0", 0, 0, 100),
+        // Always covered by "cover"
+        layer_obj("hidden", "0", 1, 20, 30),
+        layer_obj("cover", "0", 2, 10, 50),
+        layer_obj("later", "0", 1, 60, 70),
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let summarize = |lookahead: &HashMap<String, Vec<LookaheadObject>>| {
+        lookahead
+            .get("0")
+            .expect("Missing layer 0")
+            .iter()
+            .map(|obj| {
+                (
+                    obj.object_id.clone(),
+                    obj.instance.start,
+                    obj.instance.end,
+                    obj.plays,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        summarize(&states.lookahead(5, 2)),
+        vec![
+            ("cover".to_string(), 10, Some(50), true),
+            ("hidden".to_string(), 20, Some(30), false),
+        ]
+    );
+    assert_eq!(
+        summarize(&states.lookahead(20, 10)),
+        vec![("later".to_string(), 60, Some(70), true)]
+    );
+    assert!(states.lookahead(60, 10).is_empty());
+
+    // The instances are as resolved, not capped by the other objects on the layer
+    let background = &states.aspiring.get("0").expect("Missing layer 0")[0];
+    assert_eq!(background.object_id, "background");
+    assert_eq!(background.instance.end, Some(100));
+    assert!(background.plays);
+}
+
+#[test]
+fn multi_slot_layers() {
+    let mut obj_b = layer_obj("b", "mix", 1, 10, 50);
+    obj_b.keyframes = vec![SimpleKeyframe {
+        id: "kf0".to_string(),
+        // Relative to the start of b, so 15 - 45
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(5)),
+            enable_end: Some(Expression::Number(35)),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }];
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        layer_obj("a", "mix", 0, 0, 100),
+        obj_b,
+        layer_obj("c", "mix", 2, 20, 40),
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let mut layer_slots = HashMap::new();
+    layer_slots.insert(
+        "mix".to_string(),
+        LayerSlots {
+            capacity: Some(2),
+            order: SlotOrder::Start,
+        },
+    );
+    let states = resolve_all_states_with_slots(&resolved, None, &layer_slots)
+        .expect("Resolve states failed");
+    assert!(!states.state.contains_key("mix"));
+
+    let slots = |time: Time| -> Vec<(String, Time, Vec<String>)> {
+        let state = get_state(&states, time, None);
+        assert!(state.layers.is_empty());
+        state
+            .slots
+            .get("mix")
+            .map(|layer| {
+                layer
+                    .iter()
+                    .map(|obj| {
+                        let keyframes = obj.keyframes.iter().map(|k| k.info.id.clone()).collect();
+                        (obj.object_id.clone(), obj.instance.start, keyframes)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let kfs = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(slots(5), vec![("a".to_string(), 0, kfs(&[]))]);
+    assert_eq!(
+        slots(17),
+        vec![
+            ("a".to_string(), 0, kfs(&[])),
+            ("b".to_string(), 10, kfs(&["kf0"])),
+        ]
+    );
+    // c and b have the highest priorities, a has to make way
+    assert_eq!(
+        slots(25),
+        vec![
+            ("b".to_string(), 10, kfs(&["kf0"])),
+            ("c".to_string(), 20, kfs(&[])),
+        ]
+    );
+    // a comes back when c has ended, b keeps its keyframe
+    assert_eq!(
+        slots(42),
+        vec![
+            ("a".to_string(), 40, kfs(&[])),
+            ("b".to_string(), 10, kfs(&["kf0"])),
+        ]
+    );
+    assert_eq!(slots(60), vec![("a".to_string(), 40, kfs(&[]))]);
+    assert!(slots(100).is_empty());
+
+    let a = states.objects.get("a").expect("Missing object a");
+    let mut a_instances = a
+        .instances
+        .values()
+        .map(|instance| (instance.start, instance.end))
+        .collect::<Vec<_>>();
+    a_instances.sort();
+    assert_eq!(a_instances, vec![(0, Some(20)), (40, Some(100))]);
+
+    // At 20, c replaces a
+    let changes = states
+        .changes(20, 21)
+        .map(|change| {
+            (
+                change.previous.map(|state| state.object_id),
+                change.current.map(|state| state.object_id),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec![(Some("a".to_string()), Some("c".to_string()))]
+    );
+
+    // Without the configuration, only the one on top is on the layer
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    assert_obj_on_layer(&get_state(&states, 25, None), "mix", "c");
+}
+
+#[test]
+fn transitions() {
+    let mut obj_b = layer_obj("b", "0", 1, 30, 60);
+    obj_b.transition_duration = Some(10);
+    // Starts when a ends
+    let mut obj_c = layer_obj("c", "0", 0, 100, 200);
+    obj_c.transition_duration = Some(20);
+    // Starts shortly before c ends
+    let mut obj_d = layer_obj("d", "0", 1, 190, 250);
+    obj_d.transition_duration = Some(30);
+    let timeline: Vec<SimpleTimelineObj> =
+        vec![layer_obj("a", "0", 0, 0, 100), obj_b, obj_c, obj_d];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let transition = |time: Time| {
+        let state = get_state(&states, time, None);
+        let layer = state.layers.get("0").expect("Missing layer 0");
+        layer.transition.as_ref().map(|transition| {
+            (
+                layer.object_id.clone(),
+                transition.outgoing.object_id.clone(),
+                transition.outgoing.instance.end,
+                transition.start,
+                transition.end,
+                transition.progress,
+            )
+        })
+    };
+
+    assert!(transition(10).is_none());
+    // The outgoing object stays until the transition ends
+    assert_eq!(
+        transition(30),
+        Some(("b".to_string(), "a".to_string(), Some(40), 30, 40, 0.0))
+    );
+    assert_eq!(
+        transition(35),
+        Some(("b".to_string(), "a".to_string(), Some(40), 30, 40, 0.5))
+    );
+    let state = get_state(&states, 35, None);
+    let outgoing = &state.layers["0"].transition.as_ref().unwrap().outgoing;
+    assert_eq!(outgoing.remaining, Some(5));
+    assert!(transition(40).is_none());
+    // a has no transition of its own when it comes back
+    assert!(transition(65).is_none());
+    // Nothing to transition from when the replaced object has already ended
+    assert!(transition(110).is_none());
+    // The transition ends with the object it transitions from
+    assert_eq!(
+        transition(195),
+        Some(("d".to_string(), "c".to_string(), Some(200), 190, 200, 0.5))
+    );
+    assert!(transition(200).is_none());
+
+    let transition_ends = states
+        .next_events
+        .iter()
+        .filter(|event| event.event_type == EventType::TransitionEnd)
+        .map(|event| (event.time, event.object_id.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        transition_ends,
+        vec![(40, "b".to_string()), (200, "d".to_string())]
+    );
+
+    let change_times = states
+        .changes(0, 300)
+        .map(|change| change.time)
+        .collect::<Vec<_>>();
+    assert_eq!(change_times, vec![0, 30, 40, 60, 100, 190, 200, 250]);
+}
+
+#[test]
+fn layer_conflicts() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        layer_obj("a", "0", 0, 0, 100),
+        layer_obj("b", "0", 1, 20, 40),
+        layer_obj("c", "0", 0, 50, 70),
+        layer_obj("d", "0", 5, 80, 90),
+        layer_obj("e", "0", 5, 80, 90),
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let shadowed = |id: &str, reason: ConflictReason| ShadowedObject {
+        object_id: id.to_string(),
+        reason,
+    };
+    let conflicts = states
+        .conflicts
+        .get("0")
+        .expect("Missing layer 0")
+        .iter()
+        .map(|conflict| {
+            (
+                conflict.start,
+                conflict.end,
+                conflict.winners.clone(),
+                conflict.losers.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        conflicts,
+        vec![
+            (
+                20,
+                Some(40),
+                vec!["b".to_string()],
+                vec![shadowed("a", ConflictReason::Priority)]
+            ),
+            (
+                50,
+                Some(70),
+                vec!["c".to_string()],
+                vec![shadowed("a", ConflictReason::LaterStart)]
+            ),
+            (
+                80,
+                Some(90),
+                vec!["d".to_string()],
+                vec![
+                    shadowed("e", ConflictReason::IdTieBreak),
+                    shadowed("a", ConflictReason::Priority)
+                ]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn layer_gaps_and_usage() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        layer_obj("a", "pgm", 0, 0, 30),
+        layer_obj("b", "pgm", 0, 40, 45),
+        layer_obj("c", "pgm", 0, 45, 80),
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let usage = states.layer_usage(&["pgm", "empty"], 10, 100);
+
+    let pgm = usage.get("pgm").expect("Missing layer pgm");
+    assert_eq!(
+        pgm.gaps,
+        vec![
+            TimeRange { start: 30, end: 40 },
+            TimeRange {
+                start: 80,
+                end: 100
+            },
+        ]
+    );
+    assert_eq!(pgm.switches, 4);
+    assert!((pgm.occupied_percent - 60.0 * 100.0 / 90.0).abs() < 1e-9);
+    assert_eq!(
+        pgm.shortest_item,
+        Some(LayerItem {
+            object_id: "b".to_string(),
+            start: 40,
+            end: 45,
+        })
+    );
+
+    let empty = usage.get("empty").expect("Missing layer empty");
+    assert_eq!(
+        empty.gaps,
+        vec![TimeRange {
+            start: 10,
+            end: 100
+        }]
+    );
+    assert_eq!(empty.switches, 0);
+    assert_eq!(empty.occupied_percent, 0.0);
+    assert!(empty.shortest_item.is_none());
+
+    // No gaps within the first item
+    let usage = states.layer_usage(&["pgm"], 0, 30);
+    let pgm = usage.get("pgm").expect("Missing layer pgm");
+    assert!(pgm.gaps.is_empty());
+    assert_eq!(pgm.occupied_percent, 100.0);
+}
+
+#[test]
+fn content_offset_and_remaining() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        SimpleTimelineObj {
+            id: "clip".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                enable_end: Some(Expression::Number(100)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        SimpleTimelineObj {
+            id: "interruption".to_string(),
+            layer: "0".to_string(),
+            priority: 1,
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(30)),
+                enable_end: Some(Expression::Number(50)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        SimpleTimelineObj {
+            id: "group".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(20)),
+                enable_end: Some(Expression::Number(80)),
+                ..Default::default()
+            }],
+            children: Some(vec![SimpleTimelineObj {
+                id: "child".to_string(),
+                layer: "1".to_string(),
+                enable: vec![TimelineEnable {
+                    // Starts before the group, so is cut off by it
+                    enable_start: Some(Expression::String("#clip.start".to_string())),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let layer = |time: Time, layer: &str| -> (String, Time, Option<Time>, bool) {
+        let state = get_state(&states, time, None);
+        let layer = state.layers.get(layer).expect("Missing layer");
+        (
+            layer.object_id.clone(),
+            layer.content_offset,
+            layer.remaining,
+            layer.resumed,
+        )
+    };
+
+    assert_eq!(layer(10, "0"), ("clip".to_string(), 10, Some(20), false));
+    assert_eq!(
+        layer(40, "0"),
+        ("interruption".to_string(), 10, Some(10), false)
+    );
+    // The clip has kept playing while it was covered
+    assert_eq!(layer(60, "0"), ("clip".to_string(), 60, Some(40), true));
+
+    assert_eq!(layer(30, "1"), ("child".to_string(), 30, Some(50), false));
+}
+
+#[test]
+fn pauses() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        SimpleTimelineObj {
+            id: "clip".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(50)),
+                ..Default::default()
+            }],
+            pauses: vec![
+                TimelinePause {
+                    start: 20,
+                    duration: 10,
+                },
+                // After the original end, but within the end pushed by the first pause
+                TimelinePause {
+                    start: 55,
+                    duration: 5,
+                },
+            ],
+            ..Default::default()
+        },
+        SimpleTimelineObj {
+            id: "next".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::String("#clip.end".to_string())),
+                duration: Some(Expression::Number(10)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    // Both pauses push the end of the clip, and the object referring to it follows
+    assert_eq!(instance_times(&resolved, "clip"), vec![(0, Some(65))]);
+    assert_eq!(instance_times(&resolved, "next"), vec![(65, Some(75))]);
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    let layer = |time: Time| -> (String, Time, bool) {
+        let state = get_state(&states, time, None);
+        let layer = state.layers.get("0").expect("Missing layer");
+        (layer.object_id.clone(), layer.content_offset, layer.paused)
+    };
+
+    assert_eq!(layer(10), ("clip".to_string(), 10, false));
+    assert_eq!(layer(25), ("clip".to_string(), 20, true));
+    assert_eq!(layer(40), ("clip".to_string(), 30, false));
+    assert_eq!(layer(57), ("clip".to_string(), 45, true));
+    assert_eq!(layer(64), ("clip".to_string(), 49, false));
+    assert_eq!(layer(70), ("next".to_string(), 5, false));
+}
+
+#[test]
+fn pauses_of_repeats_and_groups() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        SimpleTimelineObj {
+            id: "loop".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(50)),
+                repeating: Some(Expression::Number(100)),
+                ..Default::default()
+            }],
+            pauses: vec![TimelinePause {
+                start: 10,
+                duration: 60,
+            }],
+            ..Default::default()
+        },
+        SimpleTimelineObj {
+            id: "g".to_string(),
+            layer: "1".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(100)),
+                ..Default::default()
+            }],
+            pauses: vec![TimelinePause {
+                start: 20,
+                duration: 50,
+            }],
+            children: Some(vec![SimpleTimelineObj {
+                id: "a".to_string(),
+                layer: "2".to_string(),
+                enable: vec![TimelineEnable {
+                    enable_start: Some(Expression::Number(0)),
+                    duration: Some(Expression::Number(100)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: Some(3),
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    // The paused repeat ends when the next one starts
+    assert_eq!(
+        instance_times(&resolved, "loop"),
+        vec![(0, Some(100)), (100, Some(150)), (200, Some(250))]
+    );
+    assert_eq!(instance_times(&resolved, "g"), vec![(0, Some(150))]);
+    assert_eq!(instance_times(&resolved, "a"), vec![(0, Some(100))]);
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    let layer = |time: Time, layer: &str| -> (String, Time, bool) {
+        let state = get_state(&states, time, None);
+        let layer = state.layers.get(layer).expect("Missing layer");
+        (layer.object_id.clone(), layer.content_offset, layer.paused)
+    };
+
+    assert_eq!(layer(60, "0"), ("loop".to_string(), 10, true));
+    assert_eq!(layer(120, "0"), ("loop".to_string(), 20, false));
+    // The pauses of a group don't apply to its children
+    assert_eq!(layer(60, "1"), ("g".to_string(), 20, true));
+    assert_eq!(layer(60, "2"), ("a".to_string(), 60, false));
+}
+
+#[test]
+fn priority_segments() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        layer_obj("show", "0", 1, 0, 100),
+        SimpleTimelineObj {
+            priority_segments: vec![TimelinePrioritySegment {
+                enable_while: Expression::String(".breaking_news".to_string()),
+                priority: 2,
+            }],
+            ..layer_obj("ticker", "0", 0, 0, 100)
+        },
+        SimpleTimelineObj {
+            classes: vec!["breaking_news".to_string()],
+            ..layer_obj("breaking", "1", 0, 30, 50)
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let ticker = resolved.objects.get("ticker").expect("Missing object");
+    assert_eq!(
+        ticker.resolved.priority_segments,
+        vec![ResolvedPrioritySegment {
+            start: 30,
+            end: Some(50),
+            priority: 2,
+        }]
+    );
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    assert_obj_on_layer(&get_state(&states, 10, None), "0", "show");
+    // The ticker is only on top while the breaking news plays
+    assert_obj_on_layer(&get_state(&states, 30, None), "0", "ticker");
+    assert_obj_on_layer(&get_state(&states, 49, None), "0", "ticker");
+    assert_obj_on_layer(&get_state(&states, 50, None), "0", "show");
+
+    let conflicts = states.conflicts.get("0").expect("Missing conflicts");
+    let reasons: Vec<(Time, String, ConflictReason)> = conflicts
+        .iter()
+        .map(|conflict| {
+            (
+                conflict.start,
+                conflict.losers[0].object_id.clone(),
+                conflict.losers[0].reason.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            (0, "ticker".to_string(), ConflictReason::Priority),
+            (30, "show".to_string(), ConflictReason::Priority),
+            (50, "ticker".to_string(), ConflictReason::Priority),
+        ]
+    );
+}
@@ -2,17 +2,19 @@ extern crate supertimeline;
 mod objs;
 mod util;
 
+use crate::objs::SimpleKeyframe;
 use crate::objs::SimpleTimelineObj;
 use crate::objs::TimelineObjectInstanceLight;
 use crate::util::assert_obj_on_layer;
-use crate::util::{assert_instances, assert_instances2};
+use crate::util::{assert_instances, assert_instances2, instance_times};
 use std::rc::Rc;
 use supertimeline::get_state;
 use supertimeline::NextEvent;
-use supertimeline::TimelineObjectInstance;
 use supertimeline::{
-    resolve_all_states, resolve_timeline, EventType, Expression, ResolveOptions, TimelineEnable,
+    resolve_all_states, resolve_timeline, ClassInheritance, EventType, Expression, GroupMode,
+    ResolveOptions, TimelineEnable,
 };
+use supertimeline::{Time, TimelineObjectInstance};
 
 #[test]
 fn simple_group() {
@@ -926,3 +928,745 @@ fn parent_references() {
         })],
     );
 }
+
+#[test]
+fn repeat_count_in_parent_group() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        (SimpleTimelineObj {
+            id: "group0".to_string(),
+            layer: "g0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(100)),
+                enable_end: Some(Expression::Number(128)),
+                ..Default::default()
+            }],
+            children: Some(vec![
+                (SimpleTimelineObj {
+                    id: "child0".to_string(),
+                    layer: "1".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(0)), // 100
+                        duration: Some(Expression::Number(5)),
+                        repeating: Some(Expression::Number(10)),
+                        repeat_count: Some(5), // Only 3 fit inside the group
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                (SimpleTimelineObj {
+                    id: "child1".to_string(),
+                    layer: "2".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(0)), // 100
+                        duration: Some(Expression::Number(5)),
+                        repeating: Some(Expression::Number(10)),
+                        repeat_count: Some(2),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+            ]),
+            ..Default::default()
+        }),
+    ];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: Some(99),
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let obj_child0 = resolved
+        .objects
+        .get("child0")
+        .expect("Missing child0 object");
+    let obj_child1 = resolved
+        .objects
+        .get("child1")
+        .expect("Missing child1 object");
+
+    assert_instances2(
+        &obj_child0.resolved.instances,
+        &vec![
+            Rc::new(TimelineObjectInstance {
+                start: 100,
+                end: Some(105),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 110,
+                end: Some(115),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 120,
+                end: Some(125),
+                ..Default::default()
+            }),
+        ],
+    );
+    assert_instances2(
+        &obj_child1.resolved.instances,
+        &vec![
+            Rc::new(TimelineObjectInstance {
+                start: 100,
+                end: Some(105),
+                ..Default::default()
+            }),
+            Rc::new(TimelineObjectInstance {
+                start: 110,
+                end: Some(115),
+                ..Default::default()
+            }),
+        ],
+    );
+}
+
+#[test]
+fn limit_count_in_repeating_group() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        (SimpleTimelineObj {
+            id: "group0".to_string(),
+            layer: "g0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                duration: Some(Expression::Number(50)),
+                repeating: Some(Expression::Number(100)),
+                ..Default::default()
+            }],
+            limit_count: Some(4),
+            children: Some(vec![
+                (SimpleTimelineObj {
+                    id: "child0".to_string(),
+                    layer: "1".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(0)),
+                        duration: Some(Expression::Number(10)),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                (SimpleTimelineObj {
+                    id: "child1".to_string(),
+                    layer: "2".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(0)),
+                        duration: Some(Expression::Number(5)),
+                        repeating: Some(Expression::Number(20)),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+            ]),
+            ..Default::default()
+        }),
+    ];
+
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let obj_group0 = resolved
+        .objects
+        .get("group0")
+        .expect("Missing group0 object");
+    let obj_child0 = resolved
+        .objects
+        .get("child0")
+        .expect("Missing child0 object");
+    let obj_child1 = resolved
+        .objects
+        .get("child1")
+        .expect("Missing child1 object");
+
+    let spans = |instances: &Vec<TimelineObjectInstance>| {
+        instances
+            .iter()
+            .map(|instance| (instance.start, instance.end))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        spans(&obj_group0.resolved.instances),
+        vec![
+            (0, Some(50)),
+            (100, Some(150)),
+            (200, Some(250)),
+            (300, Some(350))
+        ]
+    );
+    // Follows the group
+    assert_eq!(
+        spans(&obj_child0.resolved.instances),
+        vec![
+            (0, Some(10)),
+            (100, Some(110)),
+            (200, Some(210)),
+            (300, Some(310))
+        ]
+    );
+    // Inherits the limit of the group, so all repeats inside of every group instance are resolved
+    assert_eq!(
+        spans(&obj_child1.resolved.instances),
+        vec![
+            (0, Some(5)),
+            (20, Some(25)),
+            (40, Some(45)),
+            (100, Some(105)),
+            (120, Some(125)),
+            (140, Some(145)),
+            (200, Some(205)),
+            (220, Some(225)),
+            (240, Some(245)),
+            (300, Some(305)),
+            (320, Some(325)),
+            (340, Some(345)),
+        ]
+    );
+}
+
+#[test]
+fn group_playback_rate() {
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "replay".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(100)),
+            duration: Some(Expression::Number(100)),
+            ..Default::default()
+        }],
+        playback_rate: Some(0.5),
+        children: Some(vec![
+            SimpleTimelineObj {
+                id: "slow".to_string(),
+                layer: "0".to_string(),
+                enable: vec![TimelineEnable {
+                    enable_start: Some(Expression::Number(10)),
+                    duration: Some(Expression::Number(20)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            SimpleTimelineObj {
+                id: "after".to_string(),
+                layer: "0".to_string(),
+                enable: vec![TimelineEnable {
+                    // References are in absolute time, so are not scaled
+                    enable_start: Some(Expression::String("#slow.end + 10".to_string())),
+                    duration: Some(Expression::Number(10)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            SimpleTimelineObj {
+                id: "fast".to_string(),
+                enable: vec![TimelineEnable {
+                    enable_start: Some(Expression::Number(0)),
+                    ..Default::default()
+                }],
+                playback_rate: Some(2.0),
+                children: Some(vec![SimpleTimelineObj {
+                    id: "normal".to_string(),
+                    layer: "1".to_string(),
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(40)),
+                        duration: Some(Expression::Number(20)),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    }];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options.clone()).expect("Resolve timeline failed");
+
+    assert_eq!(instance_times(&resolved, "replay"), vec![(100, Some(200))]);
+    assert_eq!(instance_times(&resolved, "slow"), vec![(120, Some(160))]);
+    assert_eq!(instance_times(&resolved, "after"), vec![(170, Some(190))]);
+    // The rates of the groups cancel each other out
+    assert_eq!(instance_times(&resolved, "fast"), vec![(100, Some(200))]);
+    assert_eq!(instance_times(&resolved, "normal"), vec![(140, Some(160))]);
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    let state = get_state(&states, 140, None);
+    let slow = state.layers.get("0").expect("Missing layer");
+    assert_eq!(slow.object_id, "slow");
+    assert_eq!(slow.playback_rate, 0.5);
+    assert_eq!(slow.content_offset, 10);
+    let normal = state.layers.get("1").expect("Missing layer");
+    assert_eq!(normal.playback_rate, 1.0);
+    assert_eq!(normal.content_offset, 0);
+
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "stopped".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(0)),
+            ..Default::default()
+        }],
+        playback_rate: Some(0.0),
+        ..Default::default()
+    }];
+    match resolve_timeline(&timeline, options) {
+        Ok(_) => panic!("Resolved a stopped group"),
+        Err(err) => assert_eq!(format!("{:?}", err), r#"BadPlaybackRate("stopped")"#),
+    }
+}
+
+#[test]
+fn sequential_group() {
+    let item = |id: &str, start: Option<i64>, duration: i64| SimpleTimelineObj {
+        id: id.to_string(),
+        layer: id.to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: start.map(Expression::Number),
+            duration: Some(Expression::Number(duration)),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        SimpleTimelineObj {
+            id: "playlist".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(100)),
+                enable_end: Some(Expression::Number(300)),
+                ..Default::default()
+            }],
+            group_mode: GroupMode::Sequential,
+            children: Some(vec![
+                item("a", None, 30),
+                // A gap after the previous item
+                item("b", Some(10), 20),
+                // Overlaps the previous item
+                item("c", Some(-5), 20),
+                SimpleTimelineObj {
+                    disabled: true,
+                    ..item("skipped", None, 1000)
+                },
+                // Cut off by the end of the group
+                item("d", None, 200),
+                SimpleTimelineObj {
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::String("#b.start".to_string())),
+                        duration: Some(Expression::Number(5)),
+                        ..Default::default()
+                    }],
+                    ..item("referring", None, 0)
+                },
+                item("e", None, 10),
+            ]),
+            ..Default::default()
+        },
+        SimpleTimelineObj {
+            id: "loop".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(1000)),
+                duration: Some(Expression::Number(50)),
+                repeating: Some(Expression::Number(100)),
+                ..Default::default()
+            }],
+            group_mode: GroupMode::Sequential,
+            children: Some(vec![item("x", None, 20), item("y", None, 20)]),
+            ..Default::default()
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: Some(2),
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    assert_eq!(instance_times(&resolved, "a"), vec![(100, Some(130))]);
+    assert_eq!(instance_times(&resolved, "b"), vec![(140, Some(160))]);
+    assert_eq!(instance_times(&resolved, "c"), vec![(155, Some(175))]);
+    assert_eq!(instance_times(&resolved, "d"), vec![(175, Some(300))]);
+    // Is timed by its own expression, but the next item still follows it
+    assert_eq!(
+        instance_times(&resolved, "referring"),
+        vec![(140, Some(145))]
+    );
+    assert_eq!(instance_times(&resolved, "e"), vec![(145, Some(155))]);
+
+    // Each instance of the group plays the sequence
+    assert_eq!(
+        instance_times(&resolved, "x"),
+        vec![(1000, Some(1020)), (1100, Some(1120))]
+    );
+    assert_eq!(
+        instance_times(&resolved, "y"),
+        vec![(1020, Some(1040)), (1120, Some(1140))]
+    );
+}
+
+#[test]
+fn sequential_group_constant_ends() {
+    let item =
+        |id: &str, start: Option<i64>, end: Option<i64>, duration: Option<i64>| SimpleTimelineObj {
+            id: id.to_string(),
+            layer: id.to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: start.map(Expression::Number),
+                enable_end: end.map(Expression::Number),
+                duration: duration.map(Expression::Number),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "playlist".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(1000)),
+            enable_end: Some(Expression::Number(6000)),
+            ..Default::default()
+        }],
+        group_mode: GroupMode::Sequential,
+        children: Some(vec![
+            item("a", None, Some(100), None),
+            // The end is relative to where the child would start, like the start is
+            item("b", None, Some(300), None),
+            item("c", Some(50), Some(300), None),
+            item("d", None, None, Some(50)),
+        ]),
+        ..Default::default()
+    }];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    assert_eq!(instance_times(&resolved, "a"), vec![(1000, Some(1100))]);
+    assert_eq!(instance_times(&resolved, "b"), vec![(1100, Some(1400))]);
+    assert_eq!(instance_times(&resolved, "c"), vec![(1450, Some(1700))]);
+    assert_eq!(instance_times(&resolved, "d"), vec![(1700, Some(1750))]);
+}
+
+#[test]
+fn exclusive_group() {
+    let item = |id: &str, start: i64, end: Option<i64>| SimpleTimelineObj {
+        id: id.to_string(),
+        layer: id.to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(start)),
+            enable_end: end.map(Expression::Number),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "excl".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(100)),
+            enable_end: Some(Expression::Number(300)),
+            ..Default::default()
+        }],
+        group_mode: GroupMode::Exclusive,
+        children: Some(vec![
+            SimpleTimelineObj {
+                children: Some(vec![item("nested", 0, None)]),
+                ..item("a", 0, None)
+            },
+            // Ends a, which doesn't come back when b ends
+            item("b", 20, Some(40)),
+            SimpleTimelineObj {
+                priority: 1,
+                ..item("important", 60, Some(100))
+            },
+            // Can't end important, so starts when it ends
+            item("deferred", 80, Some(120)),
+            // Never gets to play
+            item("hidden", 70, Some(90)),
+        ]),
+        ..Default::default()
+    }];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    assert_eq!(instance_times(&resolved, "a"), vec![(100, Some(120))]);
+    assert_eq!(instance_times(&resolved, "nested"), vec![(100, Some(120))]);
+    assert_eq!(instance_times(&resolved, "b"), vec![(120, Some(140))]);
+    assert_eq!(
+        instance_times(&resolved, "important"),
+        vec![(160, Some(200))]
+    );
+    assert_eq!(
+        instance_times(&resolved, "deferred"),
+        vec![(200, Some(220))]
+    );
+    assert_eq!(instance_times(&resolved, "hidden"), vec![]);
+
+    let deferred = resolved.objects.get("deferred").unwrap();
+    assert_eq!(deferred.resolved.instances[0].original_start, Some(180));
+}
+
+#[test]
+fn exclusive_group_references() {
+    let item = |id: &str, start: &str, end: &str| SimpleTimelineObj {
+        id: id.to_string(),
+        layer: id.to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::String(start.to_string())),
+            enable_end: Some(Expression::String(end.to_string())),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        // Resolved before the group
+        item("after", "#a.end", "#a.end + 10"),
+        SimpleTimelineObj {
+            id: "excl".to_string(),
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                enable_end: Some(Expression::Number(1000)),
+                ..Default::default()
+            }],
+            group_mode: GroupMode::Exclusive,
+            children: Some(vec![item("a", "0", "500"), item("b", "100", "200")]),
+            ..Default::default()
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    assert_eq!(instance_times(&resolved, "a"), vec![(0, Some(100))]);
+    // The reference sees the end of a that b gave it
+    assert_eq!(instance_times(&resolved, "after"), vec![(100, Some(110))]);
+}
+
+#[test]
+fn children_outliving_parent() {
+    let child = |id: &str, start: i64, outlives_parent: bool| SimpleTimelineObj {
+        id: id.to_string(),
+        layer: id.to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(start)),
+            duration: Some(Expression::Number(100)),
+            ..Default::default()
+        }],
+        outlives_parent,
+        ..Default::default()
+    };
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "segment".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(100)),
+            duration: Some(Expression::Number(50)),
+            ..Default::default()
+        }],
+        children: Some(vec![
+            child("clip", 10, false),
+            child("bug", 10, true),
+            // Has to start while the segment plays, even if it may end after it
+            child("late", 60, true),
+        ]),
+        ..Default::default()
+    }];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    assert_eq!(instance_times(&resolved, "clip"), vec![(110, Some(150))]);
+    assert_eq!(instance_times(&resolved, "bug"), vec![(110, Some(210))]);
+    assert_eq!(instance_times(&resolved, "late"), vec![]);
+
+    // The cap is still tied to the parent, but without an end
+    let caps = |id: &str| -> Vec<(Time, Option<Time>)> {
+        let obj = resolved.objects.get(id).expect("Missing object");
+        obj.resolved.instances[0]
+            .caps
+            .iter()
+            .map(|cap| (cap.start, cap.end))
+            .collect()
+    };
+    assert_eq!(caps("clip"), vec![(100, Some(150))]);
+    assert_eq!(caps("bug"), vec![(100, None)]);
+
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+    assert_obj_on_layer(&get_state(&states, 200, None), "bug", "bug");
+}
+
+#[test]
+fn children_outliving_parent_on_layer() {
+    let child = |id: &str, outlives_parent: bool| SimpleTimelineObj {
+        id: id.to_string(),
+        layer: id.to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(10)),
+            duration: Some(Expression::Number(100)),
+            ..Default::default()
+        }],
+        outlives_parent,
+        ..Default::default()
+    };
+    let timeline: Vec<SimpleTimelineObj> = vec![SimpleTimelineObj {
+        id: "segment".to_string(),
+        layer: "seg".to_string(),
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(100)),
+            duration: Some(Expression::Number(50)),
+            ..Default::default()
+        }],
+        children: Some(vec![child("clip", false), child("bug", true)]),
+        ..Default::default()
+    }];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+    let states = resolve_all_states(&resolved, None).expect("Resolve states failed");
+
+    let state = get_state(&states, 120, None);
+    assert_obj_on_layer(&state, "seg", "segment");
+    assert_obj_on_layer(&state, "clip", "clip");
+    assert_obj_on_layer(&state, "bug", "bug");
+
+    // Stays on its layer after the parent has left its own
+    for time in &[160, 200] {
+        let state = get_state(&states, *time, None);
+        assert!(!state.layers.contains_key("seg"));
+        assert!(!state.layers.contains_key("clip"));
+        assert_obj_on_layer(&state, "bug", "bug");
+    }
+    assert!(!get_state(&states, 210, None).layers.contains_key("bug"));
+}
+
+#[test]
+fn class_inheritance() {
+    let timeline: Vec<SimpleTimelineObj> = vec![
+        SimpleTimelineObj {
+            id: "segment".to_string(),
+            classes: vec!["segment_a".to_string()],
+            class_inheritance: ClassInheritance::Children,
+            enable: vec![TimelineEnable {
+                enable_start: Some(Expression::Number(0)),
+                enable_end: Some(Expression::Number(50)),
+                ..Default::default()
+            }],
+            children: Some(vec![
+                SimpleTimelineObj {
+                    id: "bug".to_string(),
+                    layer: "1".to_string(),
+                    outlives_parent: true,
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(10)),
+                        duration: Some(Expression::Number(100)),
+                        ..Default::default()
+                    }],
+                    keyframes: vec![SimpleKeyframe {
+                        id: "bug_kf".to_string(),
+                        enable: vec![TimelineEnable {
+                            enable_start: Some(Expression::Number(0)),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                SimpleTimelineObj {
+                    id: "inner".to_string(),
+                    // Declares the class itself as well
+                    classes: vec!["segment_a".to_string(), "inner".to_string()],
+                    class_inheritance: ClassInheritance::ChildrenAndKeyframes,
+                    enable: vec![TimelineEnable {
+                        enable_start: Some(Expression::Number(0)),
+                        ..Default::default()
+                    }],
+                    children: Some(vec![SimpleTimelineObj {
+                        id: "deep".to_string(),
+                        layer: "2".to_string(),
+                        enable: vec![TimelineEnable {
+                            enable_start: Some(Expression::Number(0)),
+                            ..Default::default()
+                        }],
+                        keyframes: vec![SimpleKeyframe {
+                            id: "deep_kf".to_string(),
+                            enable: vec![TimelineEnable {
+                                enable_start: Some(Expression::Number(0)),
+                                ..Default::default()
+                            }],
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        },
+        SimpleTimelineObj {
+            id: "watcher".to_string(),
+            layer: "0".to_string(),
+            enable: vec![TimelineEnable {
+                enable_while: Some(Expression::String(".segment_a".to_string())),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    ];
+    let options = ResolveOptions {
+        time: 0,
+        limit_count: None,
+        limit_time: None,
+    };
+    let resolved = resolve_timeline(&timeline, options).expect("Resolve timeline failed");
+
+    let sorted = |ids: Option<&Vec<String>>| -> Vec<String> {
+        let mut ids = ids.cloned().unwrap_or_default();
+        ids.sort();
+        ids
+    };
+    assert_eq!(
+        sorted(resolved.classes.get("segment_a")),
+        vec!["bug", "deep", "deep_kf", "inner", "segment"]
+    );
+    // Only the keyframes below inner inherit, and only the classes passed on by inner
+    assert_eq!(
+        sorted(resolved.inherited_classes.get("segment_a")),
+        vec!["bug", "deep", "deep_kf"]
+    );
+    assert_eq!(
+        sorted(resolved.inherited_classes.get("inner")),
+        vec!["deep", "deep_kf"]
+    );
+
+    let deep = resolved.objects.get("deep").expect("Missing object");
+    assert_eq!(deep.info.inherited_classes, vec!["segment_a", "inner"]);
+
+    // The class now includes the bug, which plays on after the segment
+    let watcher = resolved.objects.get("watcher").expect("Missing object");
+    let watched: Vec<(Time, Option<Time>)> = watcher
+        .resolved
+        .instances
+        .iter()
+        .map(|instance| (instance.start, instance.end))
+        .collect();
+    assert_eq!(watched, vec![(0, Some(110))]);
+}
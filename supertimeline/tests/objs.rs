@@ -1,5 +1,8 @@
 use supertimeline::TimelineObjectInstance;
-use supertimeline::{IsTimelineKeyframe, IsTimelineObject, Time, TimelineEnable};
+use supertimeline::{
+    ClassInheritance, Expression, GroupMode, IsTimelineKeyframe, IsTimelineObject, Time,
+    TimelineEnable, TimelinePause, TimelinePrioritySegment,
+};
 
 #[derive(Default)]
 pub struct SimpleTimelineObj {
@@ -11,6 +14,15 @@ pub struct SimpleTimelineObj {
     pub disabled: bool,
     pub children: Option<Vec<SimpleTimelineObj>>,
     pub priority: i64,
+    pub limit_count: Option<usize>,
+    pub limit_time: Option<Time>,
+    pub transition_duration: Option<Time>,
+    pub pauses: Vec<TimelinePause>,
+    pub playback_rate: Option<f64>,
+    pub group_mode: GroupMode,
+    pub outlives_parent: bool,
+    pub class_inheritance: ClassInheritance,
+    pub priority_segments: Vec<TimelinePrioritySegment>,
 }
 impl IsTimelineObject<SimpleTimelineObj, SimpleKeyframe> for SimpleTimelineObj {
     fn id(&self) -> &str {
@@ -37,6 +49,49 @@ impl IsTimelineObject<SimpleTimelineObj, SimpleKeyframe> for SimpleTimelineObj {
     fn priority(&self) -> i64 {
         self.priority
     }
+    fn limit_count(&self) -> Option<usize> {
+        self.limit_count
+    }
+    fn limit_time(&self) -> Option<Time> {
+        self.limit_time
+    }
+    fn transition_duration(&self) -> Option<Time> {
+        self.transition_duration
+    }
+    fn pauses(&self) -> Option<&Vec<TimelinePause>> {
+        Some(self.pauses.as_ref())
+    }
+    fn playback_rate(&self) -> Option<f64> {
+        self.playback_rate
+    }
+    fn group_mode(&self) -> GroupMode {
+        self.group_mode.clone()
+    }
+    fn outlives_parent(&self) -> bool {
+        self.outlives_parent
+    }
+    fn class_inheritance(&self) -> ClassInheritance {
+        self.class_inheritance.clone()
+    }
+    fn priority_segments(&self) -> Option<&Vec<TimelinePrioritySegment>> {
+        Some(self.priority_segments.as_ref())
+    }
+}
+
+/** An object on a layer, enabled between two constant times */
+#[allow(dead_code)] // Not every test uses it
+pub fn layer_obj(id: &str, layer: &str, priority: i64, start: i64, end: i64) -> SimpleTimelineObj {
+    SimpleTimelineObj {
+        id: id.to_string(),
+        layer: layer.to_string(),
+        priority,
+        enable: vec![TimelineEnable {
+            enable_start: Some(Expression::Number(start)),
+            enable_end: Some(Expression::Number(end)),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
 }
 
 #[derive(Default)]
@@ -45,6 +100,8 @@ pub struct SimpleKeyframe {
     pub enable: Vec<TimelineEnable>,
     pub classes: Vec<String>,
     pub disabled: bool,
+    pub priority: i64,
+    pub duration: Option<Expression>,
 }
 impl IsTimelineKeyframe for SimpleKeyframe {
     fn id(&self) -> &str {
@@ -59,6 +116,12 @@ impl IsTimelineKeyframe for SimpleKeyframe {
     fn disabled(&self) -> bool {
         self.disabled
     }
+    fn priority(&self) -> i64 {
+        self.priority
+    }
+    fn duration(&self) -> Option<&Expression> {
+        self.duration.as_ref()
+    }
 }
 
 /**
@@ -1,21 +1,16 @@
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::sync::Mutex;
+use supertimeline::ResolvedTimeline;
+use supertimeline::Time;
 use supertimeline::TimelineObjectInstance;
 use supertimeline::TimelineState;
 
 pub fn assert_instances(
-    result: &HashMap<String, Rc<Mutex<TimelineObjectInstance>>>,
+    result: &HashMap<String, TimelineObjectInstance>,
     expected: &Vec<Rc<TimelineObjectInstance>>,
 ) {
-    let mut result_vec: Vec<TimelineObjectInstance> = result
-        .values()
-        .map(|v| {
-            let v2 = v.try_lock().unwrap();
-            v2.clone()
-        })
-        .collect();
+    let mut result_vec: Vec<TimelineObjectInstance> = result.values().cloned().collect();
     result_vec.sort_by(|a, b| {
         if a.start < b.start {
             Ordering::Less
@@ -50,6 +45,15 @@ pub fn assert_instances2(
     }
 }
 
+pub fn instance_times(resolved: &ResolvedTimeline, id: &str) -> Vec<(Time, Option<Time>)> {
+    let obj = resolved.objects.get(id).expect("Missing object");
+    obj.resolved
+        .instances
+        .iter()
+        .map(|instance| (instance.start, instance.end))
+        .collect()
+}
+
 pub fn assert_obj_on_layer(state: &TimelineState, layer: &str, id: &str) {
     let obj = state
         .layers